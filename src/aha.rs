@@ -1,61 +1,95 @@
 use super::github;
+use super::picker;
+use super::reference::ReferencePatterns;
+use super::retry::{Failure, RateLimiter, Retry};
+use super::rules;
 use super::Opt;
+use super::RepoConfig;
+use failure::Fail;
 use notify_rust::Notification;
-use regex::Regex;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
 use serde_json::Value;
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::prelude::*;
-use std::io::BufReader;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::process::Command;
 use termion::clear;
 use termion::cursor;
 use text_io::read;
 use url::Url;
 
+// the most aha allows a page
+const PER_PAGE: u64 = 200;
+
 pub struct Aha<'a> {
     pub domain: String,
     pub client: reqwest::Client,
     pub user_email: String,
     pub opt: &'a Opt,
+    pub workflow_order: Option<Vec<String>>,
+    pub workflows: RefCell<HashMap<String, Vec<Workflow>>>,
+    pub max_pages: Option<u64>,
+    pub limiter: RateLimiter,
+    pub state_path: Option<String>,
+    // "owner/repo#12 ENG-1" -> the aha status both sides last agreed on
+    pub synced: RefCell<HashMap<String, String>>,
+    // product prefixes whose workflows the config's statuses are checked
+    // against on startup
+    pub products: Option<Vec<String>>,
+    // keys open or merged pull requests link this run
+    pub live_links: RefCell<HashSet<String>>,
+    // desktop notifications, off when serving on a headless machine
+    pub notifications: bool,
 }
 
 impl<'a> Aha<'a> {
-    pub fn generate(&self) -> Result<Value, serde_json::Error> {
-        self.reset_screen();
-        println!("Enter feature name:");
-        let name: String = read!("{}\n");
+    // prompts only for what was not passed on the command line
+    pub fn generate(&self) -> Result<Feature, failure::Error> {
         self.reset_screen();
+        let name = match &self.opt.name {
+            Some(name) => name.clone(),
+            None => {
+                println!("Enter feature name:");
+                let name: String = read!("{}\n");
+                self.reset_screen();
+                name
+            }
+        };
 
-        println!("Release notes?:");
-        println!("0) No");
-        println!("1) Yes");
-        let notes: i8 = read!();
-        self.reset_screen();
+        let notes = match (self.opt.release_notes, self.opt.no_release_notes) {
+            (true, _) => true,
+            (_, true) => false,
+            _ => {
+                let choices = [
+                    picker::Choice {
+                        label: "No".to_string(),
+                        preview: "release notes are not needed".to_string(),
+                    },
+                    picker::Choice {
+                        label: "Yes".to_string(),
+                        preview: "release notes are required".to_string(),
+                    },
+                ];
+                let notes = picker::pick("Release notes?", &choices)?
+                    .ok_or_else(|| format_err!("cancelled"))?;
+                notes == 1
+            }
+        };
 
-        let mut feature = self.create_feature(name, notes).unwrap()["feature"].take();
+        let feature = self.create_feature(name, notes)?;
 
         let feature_url = self
             .url_builder()
-            .join("features/")
-            .unwrap()
-            .join(feature["id"].as_str().unwrap())
-            .unwrap();
-        let description = feature["feature"]["description"]["body"].take();
-        if !description.is_null() {
-            {
-                let mut file = File::create("/tmp/rust-workflow").unwrap();
-                file.write_all(description.to_string().as_bytes()).unwrap();
+            .join(&format!("features/{}", feature.id))?;
+        let contents = match &self.opt.description_file {
+            Some(path) => std::fs::read_to_string(path)
+                .map_err(|e| format_err!("could not read {}: {}", path, e))?,
+            None => {
+                let body = feature.description.as_ref().map(|d| d.body.as_str());
+                self.edit_description(body.unwrap_or(""))?
             }
-        }
-        Command::new("nvim")
-            .arg("/tmp/rust-workflow")
-            .status()
-            .expect("Something went wrong.");
-        let file = File::open("/tmp/rust-workflow").unwrap();
-        let mut buf_reader = BufReader::new(file);
-        let mut contents = String::new();
-        buf_reader.read_to_string(&mut contents).unwrap();
+        };
 
         let update = FeatureUpdateCreate {
             description: Some(contents),
@@ -66,70 +100,119 @@ impl<'a> Aha<'a> {
             }),
         };
 
-        let json_string = serde_json::to_string(&update)?;
-
-        if self.opt.verbose {
-            println!("puting json: {}", json_string);
-        }
-        let response = self
-            .client
-            .put(&feature_url.to_string())
-            .json(&update)
-            .send();
-        let content = response.unwrap().text();
-        let text = &content.unwrap_or("".to_string());
         if self.opt.verbose {
-            println!("updated {:?}", text);
+            println!("puting json: {}", serde_json::to_string(&update)?);
         }
-        let feature: Result<Value, _> = serde_json::from_str(&text);
-
-        if let Ok(f) = feature {
-            Ok(f)
-        } else {
-            println!("json failed to parse {:?}", text);
-            let ex: Result<_, serde_json::Error> = Err(feature.unwrap_err());
-            ex
+        let uri = feature_url.to_string();
+        let mut updated = self.send(|| self.client.put(&uri).json(&update), &uri)?;
+        Ok(decode(&uri, updated["feature"].take())?)
+    }
+    // opens $VISUAL or $EDITOR, vi when neither is set, on the description
+    pub fn edit_description(&self, body: &str) -> Result<String, failure::Error> {
+        let path = std::env::temp_dir().join("rust-workflow");
+        std::fs::write(&path, body)
+            .map_err(|e| format_err!("could not write {}: {}", path.display(), e))?;
+        let editor = std::env::var("VISUAL")
+            .or_else(|_| std::env::var("EDITOR"))
+            .unwrap_or_else(|_| "vi".to_string());
+        // the editor can carry arguments, ie "code --wait"
+        let mut words = editor.split_whitespace();
+        let program = words
+            .next()
+            .ok_or_else(|| format_err!("the editor is empty"))?;
+        let status = Command::new(program)
+            .args(words)
+            .arg(&path)
+            .status()
+            .map_err(|e| format_err!("could not run {}: {}", editor, e))?;
+        if !status.success() {
+            return Err(format_err!("{} exited with {}", editor, status));
         }
+        std::fs::read_to_string(&path)
+            .map_err(|e| format_err!("could not read {}: {}", path.display(), e))
     }
+
     pub fn url_builder(&self) -> Url {
         let uri = format!("https://{}.aha.io/api/v1/", self.domain);
         Url::parse(&uri).unwrap()
     }
 
-    pub fn status_for_labels(
-        &self,
-        labels: Vec<String>,
-        config_labels: Option<HashMap<String, String>>,
-    ) -> Option<String> {
-        let mut default_labels = HashMap::new();
-        default_labels.insert("In development".to_string(), "In development".to_string());
-        default_labels.insert(
+    // the matching label earliest in the repo's label_priority wins, labels
+    // it does not list come after it in name order
+    pub fn status_for_labels(&self, labels: &[String], repo: &RepoConfig) -> Option<String> {
+        let priority = self.label_priority(repo);
+        let matches = label_matches(labels, &self.label_statuses(repo), &priority);
+
+        let (rank, label, status) = matches.first()?;
+        if self.opt.verbose {
+            let others: Vec<&str> = matches[1..].iter().map(|m| m.1.as_str()).collect();
+            let reason = if *rank < priority.len() {
+                format!("priority {}", rank + 1)
+            } else {
+                "not in label_priority, first by name".to_string()
+            };
+            if others.is_empty() {
+                println!("label {} -> {} ({})", label, status, reason);
+            } else {
+                println!(
+                    "label {} -> {} ({}) over {}",
+                    label,
+                    status,
+                    reason,
+                    others.join(", ")
+                );
+            }
+        }
+        Some(status.clone())
+    }
+
+    // label -> status, the repo's labels on top of the defaults
+    pub fn label_statuses(&self, repo: &RepoConfig) -> HashMap<String, String> {
+        let mut label_statuses = HashMap::new();
+        label_statuses.insert("In development".to_string(), "In development".to_string());
+        label_statuses.insert(
             "Needs code review".to_string(),
             "In code review".to_string(),
         );
-        default_labels.insert("Needs PM review".to_string(), "In PM review".to_string());
-        default_labels.insert("Ready".to_string(), "Ready to ship".to_string());
-        labels
-            .iter()
-            .map(|label| {
-                let default = default_labels.get(label);
-                let x = match &config_labels {
-                    Some(c) => c.get(label).or_else(|| default),
-                    None => default,
-                };
-                match x {
-                    Some(c) => Some(c.clone()),
-                    None => None,
-                }
-            })
-            .filter(|label| label.is_some())
-            .nth(1)
-            .unwrap_or(None)
+        label_statuses.insert("Needs PM review".to_string(), "In PM review".to_string());
+        label_statuses.insert("Ready".to_string(), "Ready to ship".to_string());
+        if let Some(labels) = &repo.labels {
+            label_statuses.extend(labels.clone());
+        }
+        label_statuses
     }
-    pub fn new(domain: String, auth_token: String, email: String, opt: &Opt) -> Aha {
+
+    pub fn label_priority(&self, repo: &RepoConfig) -> Vec<String> {
+        repo.label_priority.clone().unwrap_or_else(|| {
+            vec![
+                "Ready".to_string(),
+                "Needs PM review".to_string(),
+                "Needs code review".to_string(),
+                "In development".to_string(),
+            ]
+        })
+    }
+
+    // status -> label, the inverse of label_statuses. when several labels
+    // give the same status the one first in label_priority, then by name, is
+    // used. status_labels in the repo config wins over both.
+    pub fn status_labels(&self, repo: &RepoConfig) -> HashMap<String, String> {
+        inverse_labels(
+            self.label_statuses(repo),
+            &self.label_priority(repo),
+            repo.status_labels.as_ref(),
+        )
+    }
+
+    pub fn new(
+        domain: String,
+        auth_token: String,
+        email: String,
+        opt: &Opt,
+    ) -> Result<Aha<'_>, AhaError> {
         let mut headers = reqwest::header::HeaderMap::new();
-        let mut auth =
-            reqwest::header::HeaderValue::from_str(&format!("Bearer {}", auth_token)).unwrap();
+        let mut auth = reqwest::header::HeaderValue::from_str(&format!("Bearer {}", auth_token))
+            .map_err(|_| AhaError::Client("the aha token is not a valid header".to_string()))?;
         auth.set_sensitive(true);
         headers.insert(reqwest::header::AUTHORIZATION, auth);
         headers.insert(
@@ -149,56 +232,200 @@ impl<'a> Aha<'a> {
             .default_headers(headers)
             .timeout(std::time::Duration::from_secs(50))
             .build()
-            .unwrap();
-        Aha {
+            .map_err(|error| AhaError::Client(error.to_string()))?;
+        Ok(Aha {
             client,
             domain,
             user_email: email,
             opt,
-        }
+            workflow_order: None,
+            workflows: RefCell::new(HashMap::new()),
+            max_pages: None,
+            limiter: RateLimiter::default(),
+            state_path: None,
+            synced: RefCell::new(HashMap::new()),
+            products: None,
+            live_links: RefCell::new(HashSet::new()),
+            notifications: true,
+        })
     }
 
     pub fn sync_pr(
         &self,
         pr: github::PullRequest,
-        labels: Option<HashMap<String, String>>,
+        repo: &RepoConfig,
+        github: &github::GithubEnv,
     ) -> Result<(), failure::Error> {
-        if let Some((source, key)) = self.type_from_name(&pr.name) {
+        let references = self.references_for_pr(&pr, &repo.patterns);
+        let mut linked = Vec::new();
+        let label_sync = self.opt.label_sync || repo.label_sync.unwrap_or(false);
+        if references.is_empty() && self.opt.verbose {
+            println!("Did not match {}", pr.name);
+        }
+        for (source, key, found_in) in references {
             if self.opt.verbose {
-                println!("matched {} {} {}", pr.name, source, key);
+                println!("matched {} {} {} from {}", pr.name, source, key, found_in);
             }
 
-            match self.get_json(key.clone(), source.to_string()) {
-                Ok(feature) => self.update_aha(key, pr, feature, labels, source).unwrap(),
+            match self.get_record(key.clone(), source.to_string()) {
+                Ok(feature) => {
+                    let synced_key = format!("{}#{} {}", repo.name, pr.number, key);
+                    let status = feature.workflow_status().name.clone();
+                    let last = self.synced.borrow().get(&synced_key).cloned();
+                    // the status moved in aha since the last sync, so aha wins
+                    // this round and the labels follow it. merged and closed
+                    // pull requests still apply their status.
+                    let record = if aha_moved(label_sync, &pr.state, last.as_deref(), &status) {
+                        if !self.opt.silent {
+                            println!(
+                                "{} {} moved to {} in aha, updating labels on #{}",
+                                source, key, status, pr.number
+                            );
+                        }
+                        feature
+                    } else {
+                        match self.update_aha(key.clone(), &pr, feature, repo, source, None) {
+                            Ok(record) => record,
+                            Err(error) => {
+                                println!("Error {}: {}", key, error);
+                                continue;
+                            }
+                        }
+                    };
+                    if label_sync && pr.state == "OPEN" {
+                        let status = record.workflow_status().name.clone();
+                        // labels only follow a status that changed, so a
+                        // label the sync just refused is not taken away again.
+                        // the first reference owns the labels.
+                        if linked.is_empty() && last.as_deref() != Some(status.as_str()) {
+                            self.sync_labels(&pr, &status, repo, github);
+                        }
+                        self.record_sync(synced_key, status);
+                    }
+                    linked.push((key, record));
+                }
                 Err(error) => println!("Error {}: {}", source, error),
             }
-        } else if self.opt.verbose {
-            println!("Did not match {}", pr.name);
+        }
+        if !linked.is_empty() && (self.opt.comment || repo.comment.unwrap_or(false)) {
+            let body = self.comment_body(&linked);
+            if self.opt.dry_run {
+                println!("comment on #{}:\n{}", pr.number, body);
+            } else if let Err(error) = github::upsert_comment(github, pr.number, &body) {
+                println!("Error commenting on #{}: {}", pr.number, error);
+            }
         }
         Ok(())
     }
+
+    // adds the label for the aha status and drops labels that map to a
+    // different status. labels that map to nothing are left alone.
+    pub fn sync_labels(
+        &self,
+        pr: &github::PullRequest,
+        status: &str,
+        repo: &RepoConfig,
+        github: &github::GithubEnv,
+    ) {
+        let label = match self.status_labels(repo).get(status) {
+            Some(label) => label.clone(),
+            None => {
+                if self.opt.verbose {
+                    println!("no label for {} on #{}", status, pr.number);
+                }
+                return;
+            }
+        };
+        let label_statuses = self.label_statuses(repo);
+        let remove: Vec<String> = pr
+            .labels
+            .iter()
+            .filter(|l| label_statuses.get(*l).is_some_and(|s| s != status))
+            .cloned()
+            .collect();
+        let current = pr
+            .labels
+            .iter()
+            .any(|l| label_statuses.get(l).is_some_and(|s| s == status));
+        let add = if current { vec![] } else { vec![label] };
+        if add.is_empty() && remove.is_empty() {
+            return;
+        }
+        if self.opt.dry_run || !self.opt.silent {
+            println!(
+                "labels #{} for {}: add {:?} remove {:?}",
+                pr.number, status, add, remove
+            );
+        }
+        if !self.opt.dry_run {
+            if let Err(error) = github::update_labels(github, pr.number, &add, &remove) {
+                println!("Error labeling #{}: {}", pr.number, error);
+            }
+        }
+    }
+
+    pub fn load_state(&mut self, path: String) {
+        if let Ok(text) = std::fs::read_to_string(&path) {
+            match serde_json::from_str(&text) {
+                Ok(synced) => self.synced = RefCell::new(synced),
+                Err(error) => println!("Error reading {}: {}", path, error),
+            }
+        }
+        self.state_path = Some(path);
+    }
+
+    fn record_sync(&self, synced_key: String, status: String) {
+        if self.opt.dry_run {
+            return;
+        }
+        if self.synced.borrow().get(&synced_key) == Some(&status) {
+            return;
+        }
+        self.synced.borrow_mut().insert(synced_key, status);
+        if let Some(path) = &self.state_path {
+            let text = serde_json::to_string_pretty(&*self.synced.borrow()).unwrap();
+            if let Err(error) = std::fs::write(path, text) {
+                println!("Error writing {}: {}", path, error);
+            }
+        }
+    }
+
+    pub fn comment_body(&self, linked: &[(String, Record)]) -> String {
+        let mut body = String::from(
+            "**Aha!**\n\n| Reference | Name | Status | Release |\n| --- | --- | --- | --- |\n",
+        );
+        for (key, record) in linked {
+            let reference = match record.reference_num() {
+                "" => key.as_str(),
+                reference => reference,
+            };
+            body.push_str(&format!(
+                "| [{}]({}) | {} | {} | {} |\n",
+                reference,
+                record.url(),
+                record.name(),
+                record.workflow_status().name,
+                record.release().map(|r| r.name.as_str()).unwrap_or(""),
+            ));
+        }
+        body
+    }
     pub fn generate_update_function(
         &self,
-        current: &Value,
+        current: &Record,
         pr: &github::PullRequest,
         status: Option<String>,
     ) -> FeatureUpdate {
-        let assigned = if current["assigned_to_user"].is_null() {
+        let assigned = if current.assigned_to_user().is_none() {
             Some(self.user_email.clone())
         } else {
             None
         };
-        let count = if current["custom_fields"].is_null() {
-            0
-        } else {
-            current["custom_fields"]
-                .as_array()
-                .unwrap()
-                .iter()
-                .by_ref()
-                .filter(|cf| cf["name"] == "Pull Request")
-                .count()
-        };
+        let count = current
+            .custom_fields()
+            .iter()
+            .filter(|cf| cf.name == "Pull Request")
+            .count();
         // set it if it is not there.
         let custom = if count == 0 {
             Some(CustomFieldGithub {
@@ -213,7 +440,7 @@ impl<'a> Aha<'a> {
         } else {
             None
         };
-        let current_status = &current["workflow_status"]["name"];
+        let current_status = &current.workflow_status().name;
         if status.is_none()
             && (current_status == "Ready to develop" || current_status == "Under consideration")
         {
@@ -237,36 +464,139 @@ impl<'a> Aha<'a> {
             );
         }
     }
-    pub fn create_feature(&self, name: String, notes: i8) -> Result<Value, serde_json::Error> {
-        let projects_url = self.url_builder().join("products").unwrap();
-        let projects = self.get(projects_url, "products".to_string()).unwrap();
-        let projects = projects.as_array().unwrap();
-        for (index, project) in projects.iter().enumerate() {
-            println!("{}) {} ({})", index, project["name"], project["id"]);
+    // the item whose id or name is given, otherwise the one picked from a list
+    pub fn choose<T: Pick>(
+        &self,
+        mut items: Vec<T>,
+        kind: &str,
+        given: Option<&String>,
+    ) -> Result<T, failure::Error> {
+        if items.is_empty() {
+            return Err(format_err!("no {}s to choose from", kind));
         }
-        println!("Choose a product:");
-        let index: usize = read!();
-        self.reset_screen();
+        let index = match given {
+            Some(given) => items
+                .iter()
+                .position(|item| item.matches(given))
+                .ok_or_else(|| format_err!("no {} named {}", kind, given))?,
+            None => {
+                let choices: Vec<picker::Choice> = items.iter().map(Pick::choice).collect();
+                picker::pick(&format!("Choose a {}", kind), &choices)?
+                    .ok_or_else(|| format_err!("no {} chosen", kind))?
+            }
+        };
+        Ok(items.swap_remove(index))
+    }
+
+    pub fn choose_release(&self) -> Result<Release, failure::Error> {
+        let projects_url = self.url_builder().join("products").unwrap();
+        let projects: Vec<Product> = self.list(projects_url, "products".to_string())?;
+        let project = self.choose(projects, "product", self.opt.product.as_ref())?;
 
         let releases_url = self
             .url_builder()
             .join("products/")
             .unwrap()
-            .join(&format!("{}/", projects[index]["id"].as_str().unwrap()))
+            .join(&format!("{}/", project.id))
             .unwrap()
             .join("releases?exclude_shipped=true")
             .unwrap();
-        let releases = self.get(releases_url, "releases".to_string()).unwrap();
-        let releases = releases.as_array().unwrap();
-        for (index, release) in releases.iter().enumerate() {
-            println!("{}) {} ({})", index, release["name"], release["id"]);
+        let releases: Vec<Release> = self.list(releases_url, "releases".to_string())?;
+        self.choose(releases, "release", self.opt.release.as_ref())
+    }
+
+    // pick a feature assigned to you or in a release, None when there are none
+    pub fn choose_start(&self) -> Result<Option<Feature>, failure::Error> {
+        let from = if self.opt.product.is_some() || self.opt.release.is_some() {
+            1
+        } else {
+            let choices = [
+                picker::Choice {
+                    label: "Features assigned to me".to_string(),
+                    preview: self.user_email.clone(),
+                },
+                picker::Choice {
+                    label: "A release".to_string(),
+                    preview: "pick a product and release".to_string(),
+                },
+            ];
+            picker::pick("Start from", &choices)?.ok_or_else(|| format_err!("cancelled"))?
+        };
+
+        let fields = "fields=reference_num,name,url,workflow_status,assigned_to_user,project";
+        let features_url = if from == 1 {
+            let release = self.choose_release()?;
+            self.url_builder()
+                .join(&format!("releases/{}/features?{}", release.id, fields))
+                .unwrap()
+        } else {
+            let mut url = self
+                .url_builder()
+                .join(&format!("features?{}", fields))
+                .unwrap();
+            url.query_pairs_mut()
+                .append_pair("assigned_to_user", &self.user_email);
+            url
+        };
+        let mut features: Vec<Feature> = self.list(features_url, "features".to_string())?;
+        features.retain(|feature| !feature.workflow_status.complete);
+        if features.is_empty() {
+            println!("No open features found");
+            return Ok(None);
         }
-        println!("Choose a release:");
-        let index: usize = read!();
-        self.reset_screen();
+        Ok(Some(self.choose(features, "feature", None)?))
+    }
+
+    // move the feature to in development unless that is a step back, returns
+    // the updated feature
+    pub fn start(&self, feature: Feature) -> Result<Feature, failure::Error> {
+        let status = "In development".to_string();
+        let record = Record::Feature(feature.clone());
+        let workflow_status = if !self.opt.allow_backwards && self.moves_backwards(&record, &status)
+        {
+            if !self.opt.silent {
+                println!(
+                    "Leaving {} in {}: {} moves backwards",
+                    feature.reference_num, feature.workflow_status.name, status
+                );
+            }
+            None
+        } else {
+            Some(WorkflowStatusUpdate { name: status })
+        };
+        let update = FeatureUpdateCreate {
+            description: None,
+            assigned_to_user: if feature.assigned_to_user.is_none() {
+                Some(self.user_email.clone())
+            } else {
+                None
+            },
+            custom_fields: None,
+            workflow_status,
+        };
+        if update.assigned_to_user.is_none() && update.workflow_status.is_none() {
+            return Ok(feature);
+        }
+        let feature_url = self
+            .url_builder()
+            .join(&format!("features/{}", feature.reference_num))
+            .unwrap();
+        if self.opt.verbose {
+            println!("puting json: {}", serde_json::to_string(&update)?);
+        }
+        if self.opt.dry_run {
+            return Ok(feature);
+        }
+        let uri = feature_url.to_string();
+        let mut updated = self.send(|| self.client.put(&uri).json(&update), &uri)?;
+        Ok(decode(&uri, updated["feature"].take())?)
+    }
+
+    pub fn create_feature(&self, name: String, notes: bool) -> Result<Feature, failure::Error> {
+        let release = self.choose_release()?;
 
         let uri = format!("https://{}.aha.io/api/v1/features", self.domain);
-        let notes_required = if notes == 1 {
+        let notes_required = if notes {
             Some(CustomNotes {
                 notes: "Required".to_string(),
             })
@@ -276,112 +606,518 @@ impl<'a> Aha<'a> {
 
         let feature = FeatureCreate {
             name,
-            release_id: releases[index]["id"].as_str().unwrap().to_string(),
+            release_id: release.id,
             custom_fields: notes_required,
         };
         let json_string = serde_json::to_string(&feature)?;
         if self.opt.verbose {
             println!("creating feature json: {}", json_string);
         }
-        let response = self.client.post(&uri).json(&feature).send();
-        let content = response.unwrap().text();
-        let text = &content.unwrap_or("".to_string());
+        let mut created = self.send_create(|| self.client.post(&uri).json(&feature), &uri)?;
+        Ok(decode(&uri, created["feature"].take())?)
+    }
+
+    pub fn moves_backwards(&self, current: &Record, target: &str) -> bool {
+        let current_status = &current.workflow_status().name;
+        let order = match self.workflow_order(current) {
+            Some(order) => order,
+            None => {
+                if self.opt.verbose {
+                    println!("no workflow order for {}", current_status);
+                }
+                return false;
+            }
+        };
+        backwards(&order, current_status, target).unwrap_or(false)
+    }
+
+    // status names of the record's workflow in order, from the aha config or
+    // the product's workflows
+    pub fn workflow_order(&self, current: &Record) -> Option<Vec<String>> {
+        if let Some(order) = &self.workflow_order {
+            return Some(order.clone());
+        }
+        self.record_workflow(current)
+    }
+
+    // status names of the workflow the record is in, in order. products are
+    // only fetched once a run.
+    fn record_workflow(&self, current: &Record) -> Option<Vec<String>> {
+        let product_id = current.product_id()?.to_string();
+        self.load_workflows(&product_id).ok()?;
+
+        let status_id = &current.workflow_status().id;
+        let workflows = self.workflows.borrow();
+        let workflow = workflows.get(&product_id)?.iter().find(|workflow| {
+            workflow
+                .workflow_statuses
+                .iter()
+                .any(|status| &status.id == status_id)
+        })?;
+        let mut statuses = workflow.workflow_statuses.clone();
+        statuses.sort_by_key(|status| status.position.unwrap_or(0));
+        Some(statuses.into_iter().map(|status| status.name).collect())
+    }
+
+    fn load_workflows(&self, product_id: &str) -> Result<(), AhaError> {
+        if self.workflows.borrow().contains_key(product_id) {
+            return Ok(());
+        }
+        let url = self
+            .url_builder()
+            .join(&format!("products/{}/workflows", product_id))
+            .map_err(|e| AhaError::Client(e.to_string()))?;
+        let workflows: Vec<Workflow> = self.get(url, "workflows".to_string())?;
+        self.workflows
+            .borrow_mut()
+            .insert(product_id.to_string(), workflows);
+        Ok(())
+    }
+
+    // every status name the config moves records to or matches on, must be in
+    // the workflows of the products listed under [aha]. unknown names fail
+    // with the closest known one. records are checked against their own
+    // product's workflow when they are updated either way.
+    pub fn check_statuses(&self, repos: &[RepoConfig]) -> Result<(), failure::Error> {
+        let products = match &self.products {
+            Some(products) => products,
+            None => {
+                if self.opt.verbose {
+                    println!("no products under [aha], not checking status names");
+                }
+                return Ok(());
+            }
+        };
+        let mut known: Vec<String> = Vec::new();
+        for product in products {
+            if let Err(error) = self.load_workflows(product) {
+                if !self.opt.silent {
+                    println!("Could not read the workflows of {}: {}", product, error);
+                }
+                continue;
+            }
+            let workflows = self.workflows.borrow();
+            for workflow in workflows.get(product).into_iter().flatten() {
+                for status in &workflow.workflow_statuses {
+                    if !known.contains(&status.name) {
+                        known.push(status.name.clone());
+                    }
+                }
+            }
+        }
+        if known.is_empty() {
+            if !self.opt.silent {
+                println!("no aha workflow statuses found, not checking the config");
+            }
+            return Ok(());
+        }
+        if self.opt.verbose {
+            println!("aha statuses: {}", known.join(", "));
+        }
+
+        // status -> where the config uses it
+        let mut used: Vec<(String, Vec<String>)> = Vec::new();
+        let mut use_status =
+            |status: &str, source: String| match used.iter_mut().find(|(s, _)| s == status) {
+                Some((_, sources)) => sources.push(source),
+                None => used.push((status.to_string(), vec![source])),
+            };
+        for status in self.workflow_order.iter().flatten() {
+            use_status(status, "workflow_order".to_string());
+        }
+        for repo in repos {
+            let mut labels: Vec<(String, String)> = self.label_statuses(repo).into_iter().collect();
+            labels.sort();
+            for (label, status) in labels {
+                use_status(&status, format!("{} label {}", repo.name, label));
+            }
+            let merged = repo.merged_status.as_deref().unwrap_or("Ready to ship");
+            use_status(merged, format!("{} merged_status", repo.name));
+            let closed = match (&repo.closed_status, repo.closed_action) {
+                (Some(status), _) => Some(status.as_str()),
+                (None, Some(ClosedAction::Revert)) => Some("Ready to develop"),
+                _ => None,
+            };
+            if let Some(closed) = closed {
+                use_status(closed, format!("{} closed_status", repo.name));
+            }
+            for rule in repo.rules.iter().flatten() {
+                use_status(&rule.status, format!("{} rule {}", repo.name, rule.name));
+                for status in rule.current_status.iter().flatten() {
+                    use_status(
+                        status,
+                        format!("{} rule {} current_status", repo.name, rule.name),
+                    );
+                }
+            }
+        }
+
+        let unknown: Vec<String> = used
+            .iter()
+            .filter(|(status, _)| !known.contains(status))
+            .map(|(status, sources)| {
+                format!(
+                    "{:?} ({}), did you mean {:?}?",
+                    status,
+                    sources.join(", "),
+                    closest(status, &known)
+                )
+            })
+            .collect();
+        if unknown.is_empty() {
+            return Ok(());
+        }
+        Err(format_err!(
+            "unknown aha workflow status:\n  {}",
+            unknown.join("\n  ")
+        ))
+    }
+
+    // remember the keys of pull requests that are still open or merged, a
+    // closed one does not revert a record they carry
+    pub fn note_links(&self, prs: &[github::PullRequest], repo: &RepoConfig) {
+        let mut live_links = self.live_links.borrow_mut();
+        for pr in prs.iter().filter(|pr| pr.state != "CLOSED") {
+            for (_, key, _) in self.references_for_pr(pr, &repo.patterns) {
+                live_links.insert(key);
+            }
+        }
+    }
+
+    // desktop notification, a machine without a notification daemon gets a
+    // printed warning instead
+    fn notify(&self, summary: &str, body: &str) {
+        if self.opt.silent || !self.notifications {
+            return;
+        }
+        let shown = Notification::new()
+            .summary(summary)
+            .body(body)
+            .icon("firefox")
+            .timeout(0)
+            .show();
+        if let Err(error) = shown {
+            println!("Could not notify {}: {}", summary, error);
+        }
+    }
+
+    // a closed pull request is only acted on once, the state file keeps track
+    fn closed_key(repo: &RepoConfig, pr: &github::PullRequest, key: &str) -> String {
+        format!("{}#{} {} closed", repo.name, pr.number, key)
+    }
+
+    // a pull request closed without merging
+    pub fn closed_update(
+        &self,
+        key: &str,
+        pr: &github::PullRequest,
+        current: &Record,
+        repo: &RepoConfig,
+    ) -> FeatureUpdate {
+        let mut update = FeatureUpdate {
+            assigned_to_user: None,
+            custom_fields: None,
+            workflow_status: None,
+        };
+        if self
+            .synced
+            .borrow()
+            .contains_key(&Aha::closed_key(repo, pr, key))
+        {
+            if self.opt.verbose {
+                println!("closed #{} {} already handled", pr.number, key);
+            }
+            return update;
+        }
+        match repo.closed_action.unwrap_or(ClosedAction::Notify) {
+            ClosedAction::Revert => {
+                let status = repo
+                    .closed_status
+                    .clone()
+                    .unwrap_or_else(|| "Ready to develop".to_string());
+                let merged = repo.merged_status.as_deref().unwrap_or("Ready to ship");
+                let order = self.workflow_order(current);
+                // not while another pull request carries the work on
+                let revert = if self.live_links.borrow().contains(key) {
+                    Err("another pull request links it".to_string())
+                } else {
+                    should_revert(order.as_deref(), current.workflow_status(), &status, merged)
+                };
+                match revert {
+                    Ok(true) => {
+                        update.workflow_status = Some(WorkflowStatusUpdate { name: status })
+                    }
+                    Ok(false) => (),
+                    Err(reason) => {
+                        if !self.opt.silent {
+                            println!(
+                                "Not reverting {} to {} for closed #{}: {}",
+                                key, status, pr.number, reason
+                            );
+                        }
+                    }
+                }
+            }
+            ClosedAction::Clear => {
+                let linked = current
+                    .custom_fields()
+                    .iter()
+                    .any(|cf| cf.name == "Pull Request" && cf.value == pr.url.as_str());
+                if linked {
+                    update.custom_fields = Some(CustomFieldGithub {
+                        github_url: "".to_string(),
+                    });
+                }
+            }
+            ClosedAction::Notify => {
+                self.notify(
+                    &format!("Closed without merging {}", key),
+                    &format!("{}\n{}", pr.url, pr.number),
+                );
+            }
+        }
         if self.opt.verbose {
-            println!("created {:?}", text);
+            println!("closed #{} {}: {:?}", pr.number, key, update);
         }
+        update
+    }
 
-        serde_json::from_str(&text)
+    // rules first, then merged_status for merged pull requests, then labels
+    fn status_for_pr(
+        &self,
+        pr: &github::PullRequest,
+        current_status: &str,
+        repo: &RepoConfig,
+        explain: bool,
+    ) -> Option<String> {
+        let rules = repo.rules.as_deref().unwrap_or(&[]);
+        match rules::evaluate(rules, pr, current_status, explain) {
+            Some(rule) => Some(rule.status.clone()),
+            None if pr.state == "MERGED" => Some(
+                repo.merged_status
+                    .clone()
+                    .unwrap_or_else(|| "Ready to ship".to_string()),
+            ),
+            None => self.status_for_labels(&pr.labels, repo),
+        }
     }
 
     pub fn update_aha(
         &self,
         key: String,
-        pr: github::PullRequest,
-        current: Value,
-        labels: Option<HashMap<String, String>>,
+        pr: &github::PullRequest,
+        current: Record,
+        repo: &RepoConfig,
         base: String,
-    ) -> Result<(), serde_json::Error> {
+        target: Option<String>,
+    ) -> Result<Record, AhaError> {
         let uri = format!("https://{}.aha.io/api/v1/{}s/{}", self.domain, base, key);
-        let status = self.status_for_labels(pr.labels.clone(), labels);
-        let feature = self.generate_update_function(&current, &pr, status);
-        let json_string = serde_json::to_string(&feature)?;
+        let current_status = current.workflow_status().name.as_str();
+        let feature = if pr.state == "CLOSED" {
+            self.closed_update(&key, pr, &current, repo)
+        } else {
+            let explain = self.opt.dry_run || self.opt.verbose;
+            // a status the caller asks for wins over rules and labels
+            let status = target.or_else(|| self.status_for_pr(pr, current_status, repo, explain));
+            let allow_backwards = self.opt.allow_backwards || repo.allow_backwards.unwrap_or(false);
+            let status = match status {
+                Some(target) if !allow_backwards && self.moves_backwards(&current, &target) => {
+                    if !self.opt.silent {
+                        println!(
+                            "Skipping {} {}: {} -> {} moves backwards",
+                            base, key, current_status, target
+                        );
+                    }
+                    None
+                }
+                status => status,
+            };
+            self.generate_update_function(&current, pr, status)
+        };
+        if let (Some(status), Some(known)) =
+            (&feature.workflow_status, self.record_workflow(&current))
+        {
+            if !known.contains(&status.name) {
+                return Err(AhaError::UnknownStatus {
+                    key,
+                    status: status.name.clone(),
+                    suggestion: closest(&status.name, &known).to_string(),
+                });
+            }
+        }
+        let json_string = serde_json::to_string(&feature).map_err(|error| AhaError::Decode {
+            url: uri.clone(),
+            error,
+        })?;
         if self.opt.verbose {
             println!("puting {} json: {} | {}", base, json_string, uri);
         }
-        if !self.opt.silent && json_string.len() > 4 && !current["url"].is_null() {
-            Notification::new()
-                .summary(&format!("Updating requirement {}", key))
-                .body(&format!(
-                    "{}\n{}",
-                    current["url"].as_str().unwrap(),
-                    pr.number
-                ))
-                .icon("firefox")
-                .timeout(0)
-                .show()
-                .unwrap();
+        if json_string.len() > 4 && !current.url().is_empty() {
+            self.notify(
+                &format!("Updating requirement {}", key),
+                &format!("{}\n{}", current.url(), pr.number),
+            );
         }
-        if !self.opt.dry_run && json_string.len() > 4 {
-            let response = self.client.put(&uri).json(&feature).send();
-            let content = response.unwrap().text();
-            let text = &content.unwrap_or("".to_string());
-            if self.opt.verbose {
-                println!("updated {} {:?}", base, text);
+        let record = if !self.opt.dry_run && json_string.len() > 4 {
+            let mut f = self.send(|| self.client.put(&uri).json(&feature), &uri)?;
+            if f[&base].is_null() {
+                current
+            } else {
+                Record::from_value(&base, f[&base].take())
+                    .map_err(|error| AhaError::Decode { url: uri, error })?
             }
-            let feature: Result<Value, _> = serde_json::from_str(&text);
+        } else {
+            current
+        };
+        if pr.state == "CLOSED" {
+            let action = format!("{:?}", repo.closed_action.unwrap_or(ClosedAction::Notify));
+            self.record_sync(Aha::closed_key(repo, pr, &key), action.to_lowercase());
+        }
+        Ok(record)
+    }
 
-            if let Ok(f) = feature {
-                if f[base].is_null() {
-                    println!("json failed to parse {:?}", text);
-                }
-                Ok(())
-            } else {
-                if self.opt.verbose {
-                    println!("json failed to parse {:?}", text);
+    // every reference in the title, the branch name --generate hands out,
+    // "Aha: ENG-1" lines or aha links in the body and commit trailers, newest
+    // commit first. a key found in more than one place is kept once, from the
+    // first of those sources.
+    pub fn references_for_pr(
+        &self,
+        pr: &github::PullRequest,
+        patterns: &ReferencePatterns,
+    ) -> Vec<(String, String, &'static str)> {
+        let mut sources = vec![
+            ("title", patterns.find(&pr.name)),
+            ("branch", patterns.find(&pr.head_ref)),
+            ("body", patterns.find_in_text(&pr.body, true)),
+        ];
+        for message in pr.commits.iter().rev() {
+            sources.push(("commit", patterns.find_in_text(message, false)));
+        }
+        let mut found: Vec<(String, String, &'static str)> = Vec::new();
+        for (found_in, references) in sources {
+            for (source, key) in references {
+                if !found.iter().any(|(_, existing, _)| *existing == key) {
+                    found.push((source, key, found_in));
                 }
-                let ex: Result<(), serde_json::Error> = Err(feature.unwrap_err());
-                ex
             }
-        } else {
-            Ok(())
         }
+        found
     }
 
-    pub fn type_from_name(&self, name: &str) -> Option<(String, String)> {
-        //could return enum
-        let req = Regex::new(r"^([A-Z]+-\d+-\d+)").unwrap();
-        let fet = Regex::new(r"^([A-Z]{1,}-\d{1,})").unwrap();
-        let rc = req.captures(&name.trim());
-        let fc = fet.captures(&name.trim());
-        if let Some(rc) = rc {
-            Some(("requirement".to_string(), rc[0].to_string()))
-        } else if let Some(fc) = fc {
-            Some(("feature".to_string(), fc[0].to_string()))
-        } else {
-            None
+    pub fn get<T: DeserializeOwned>(&self, url: Url, base: String) -> Result<T, AhaError> {
+        let uri = url.to_string();
+        let mut fe = self.fetch(url, &base)?;
+        decode(&uri, fe[base].take())
+    }
+
+    // every page of a list endpoint, stopping after max_pages when it is set
+    pub fn list<T: DeserializeOwned>(&self, url: Url, base: String) -> Result<Vec<T>, AhaError> {
+        let mut items = Vec::new();
+        let mut page = 1;
+        loop {
+            let mut page_url = url.clone();
+            page_url
+                .query_pairs_mut()
+                .append_pair("page", &page.to_string())
+                .append_pair("per_page", &PER_PAGE.to_string());
+            let uri = page_url.to_string();
+            let mut response = self.fetch(page_url, &base)?;
+            let batch: Vec<T> = decode(&uri, response[&base].take())?;
+            items.extend(batch);
+
+            let pagination: Option<Pagination> = decode(&uri, response["pagination"].take())?;
+            let pagination = pagination.unwrap_or_default();
+            if page >= pagination.total_pages {
+                break;
+            }
+            if self.max_pages.is_some_and(|max| page >= max) {
+                if !self.opt.silent {
+                    println!(
+                        "Stopped {} after {} of {} pages, {} of {} records",
+                        base,
+                        page,
+                        pagination.total_pages,
+                        items.len(),
+                        pagination.total_records
+                    );
+                }
+                break;
+            }
+            page += 1;
         }
+        Ok(items)
     }
 
-    pub fn get(&self, url: Url, base: String) -> Result<Value, serde_json::Error> {
+    fn fetch(&self, url: Url, base: &str) -> Result<Value, AhaError> {
         let uri = url.to_string();
         if self.opt.verbose {
             println!("{} url: {}", base, uri);
         }
-        let response = self.client.get(&uri).send();
-        let content = response.unwrap().text();
+        self.send(|| self.client.get(&uri), &uri)
+    }
+
+    // the json body of a 2xx response, anything else is the matching AhaError
+    // 429 and 5xx responses are retried
+    fn send<F>(&self, build: F, url: &str) -> Result<Value, AhaError>
+    where
+        F: Fn() -> reqwest::RequestBuilder,
+    {
+        self.send_with(build, url, true)
+    }
+
+    // for posts that create a record. a lost response may still have created
+    // it, so only a failed connect or a 429 is retried
+    fn send_create<F>(&self, build: F, url: &str) -> Result<Value, AhaError>
+    where
+        F: Fn() -> reqwest::RequestBuilder,
+    {
+        self.send_with(build, url, false)
+    }
+
+    fn send_with<F>(&self, build: F, url: &str, repeatable: bool) -> Result<Value, AhaError>
+    where
+        F: Fn() -> reqwest::RequestBuilder,
+    {
+        let transport = |error| AhaError::Transport {
+            url: url.to_string(),
+            error,
+        };
+        let retry = Retry {
+            service: "aha",
+            silent: self.opt.silent,
+            limiter: Some(&self.limiter),
+        };
+        let (status, text) = match retry.send(build, repeatable) {
+            Ok(mut response) => (response.status(), response.text().map_err(transport)?),
+            Err(Failure::Transport(error)) => return Err(transport(error)),
+            Err(Failure::Status { status, text, .. }) => (status, text),
+        };
         if self.opt.verbose {
-            println!("{} text {:?}", base, content);
+            println!("{} {} text {:?}", status, url, text);
         }
-        let feature: Result<Value, _> = serde_json::from_str(&content.unwrap_or("".to_string()));
-        if let Ok(mut fe) = feature {
-            Ok(fe[base].take())
-        } else {
-            let ex: Result<Value, serde_json::Error> = Err(feature.unwrap_err());
-            ex
+        if !status.is_success() {
+            return Err(AhaError::from_response(url, status, &text));
         }
+        if text.trim().is_empty() {
+            return Ok(Value::Null);
+        }
+        serde_json::from_str(&text).map_err(|error| AhaError::Decode {
+            url: url.to_string(),
+            error,
+        })
+    }
+
+    // the feature, requirement or epic behind a reference
+    pub fn get_record(&self, key: String, base: String) -> Result<Record, AhaError> {
+        let record: Value = self.get_json(key.clone(), base.clone())?;
+        Record::from_value(&base, record).map_err(|error| AhaError::Decode { url: key, error })
     }
 
-    pub fn get_json(&self, end_path: String, base: String) -> Result<Value, serde_json::Error> {
+    pub fn get_json<T: DeserializeOwned>(
+        &self,
+        end_path: String,
+        base: String,
+    ) -> Result<T, AhaError> {
         let uri = format!("https://{}.aha.io/api/v1/", self.domain);
         let url = Url::parse(&uri).unwrap();
 
@@ -395,6 +1131,143 @@ impl<'a> Aha<'a> {
     }
 }
 
+fn decode<T: DeserializeOwned>(url: &str, value: Value) -> Result<T, AhaError> {
+    serde_json::from_value(value).map_err(|error| AhaError::Decode {
+        url: url.to_string(),
+        error,
+    })
+}
+
+#[derive(Debug)]
+pub enum AhaError {
+    Client(String),
+    Transport {
+        url: String,
+        error: reqwest::Error,
+    },
+    Auth {
+        url: String,
+        status: u16,
+        message: String,
+    },
+    NotFound {
+        url: String,
+    },
+    Validation {
+        url: String,
+        message: String,
+    },
+    RateLimited {
+        url: String,
+        message: String,
+    },
+    Status {
+        url: String,
+        status: u16,
+        message: String,
+    },
+    Decode {
+        url: String,
+        error: serde_json::Error,
+    },
+    UnknownStatus {
+        key: String,
+        status: String,
+        suggestion: String,
+    },
+}
+
+impl fmt::Display for AhaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AhaError::Client(message) => write!(f, "aha client: {}", message),
+            AhaError::Transport { url, error } => {
+                write!(f, "aha request to {} failed: {}", url, error)
+            }
+            AhaError::Auth {
+                url,
+                status,
+                message,
+            } => write!(
+                f,
+                "aha refused {} ({}), check AHA_TOKEN: {}",
+                url, status, message
+            ),
+            AhaError::NotFound { url } => write!(f, "aha has nothing at {}", url),
+            AhaError::Validation { url, message } => {
+                write!(f, "aha rejected the change to {}: {}", url, message)
+            }
+            AhaError::RateLimited { url, message } => {
+                write!(f, "aha rate limited {} after retrying: {}", url, message)
+            }
+            AhaError::Status {
+                url,
+                status,
+                message,
+            } => write!(f, "aha {} returned {}: {}", url, status, message),
+            AhaError::Decode { url, error } => {
+                write!(f, "aha json for {} did not decode: {}", url, error)
+            }
+            AhaError::UnknownStatus {
+                key,
+                status,
+                suggestion,
+            } => write!(
+                f,
+                "{} is not in the aha workflow of {}, did you mean {}?",
+                status, key, suggestion
+            ),
+        }
+    }
+}
+
+impl Fail for AhaError {
+    fn cause(&self) -> Option<&dyn Fail> {
+        match self {
+            AhaError::Transport { error, .. } => Some(error),
+            AhaError::Decode { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl AhaError {
+    // aha explains errors as {"errors": {"message": ".."}} or {"error": ".."}
+    pub fn from_response(url: &str, status: reqwest::StatusCode, body: &str) -> AhaError {
+        let json: Value = serde_json::from_str(body).unwrap_or(Value::Null);
+        let message = json["errors"]["message"]
+            .as_str()
+            .or_else(|| json["error"].as_str())
+            .or_else(|| json["message"].as_str())
+            .map(String::from)
+            .unwrap_or_else(|| body.chars().take(200).collect());
+        let url = url.to_string();
+        match status.as_u16() {
+            401 | 403 => AhaError::Auth {
+                url,
+                status: status.as_u16(),
+                message,
+            },
+            404 => AhaError::NotFound { url },
+            422 => AhaError::Validation { url, message },
+            429 => AhaError::RateLimited { url, message },
+            status => AhaError::Status {
+                url,
+                status,
+                message,
+            },
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ClosedAction {
+    Revert,
+    Clear,
+    Notify,
+}
+
 // keep
 #[derive(Serialize, Debug, Deserialize)]
 pub struct FeatureCreate {
@@ -445,3 +1318,600 @@ pub struct CustomFieldGithub {
     #[serde(rename = "pull_request")]
     github_url: String,
 }
+
+// null and missing fields fall back to the default instead of failing the
+// whole record
+fn null_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+// fields the api sends that are not modeled stay in extra
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Feature {
+    pub id: String,
+    pub reference_num: String,
+    pub name: String,
+    pub url: String,
+    #[serde(deserialize_with = "null_default")]
+    pub workflow_status: WorkflowStatus,
+    pub assigned_to_user: Option<User>,
+    #[serde(deserialize_with = "null_default")]
+    pub custom_fields: Vec<CustomField>,
+    pub description: Option<Description>,
+    pub release: Option<Release>,
+    pub project: Option<Product>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Requirement {
+    pub id: String,
+    pub reference_num: String,
+    pub name: String,
+    pub url: String,
+    #[serde(deserialize_with = "null_default")]
+    pub workflow_status: WorkflowStatus,
+    pub assigned_to_user: Option<User>,
+    #[serde(deserialize_with = "null_default")]
+    pub custom_fields: Vec<CustomField>,
+    pub description: Option<Description>,
+    pub feature: Option<Box<Feature>>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Product {
+    pub id: String,
+    pub reference_prefix: String,
+    pub name: String,
+    #[serde(flatten)]
+    pub extra: HashMap<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Release {
+    pub id: String,
+    pub reference_num: String,
+    pub name: String,
+    pub release_date: Option<String>,
+    pub project: Option<Product>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Workflow {
+    pub id: String,
+    pub name: String,
+    #[serde(deserialize_with = "null_default")]
+    pub workflow_statuses: Vec<WorkflowStatus>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct WorkflowStatus {
+    pub id: String,
+    pub name: String,
+    pub position: Option<i64>,
+    // shipped, done and will not implement
+    #[serde(deserialize_with = "null_default")]
+    pub complete: bool,
+    #[serde(flatten)]
+    pub extra: HashMap<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct User {
+    pub id: String,
+    pub name: String,
+    pub email: String,
+    #[serde(flatten)]
+    pub extra: HashMap<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct CustomField {
+    pub key: String,
+    pub name: String,
+    pub value: Value,
+    #[serde(flatten)]
+    pub extra: HashMap<String, Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Description {
+    pub body: String,
+    #[serde(flatten)]
+    pub extra: HashMap<String, Value>,
+}
+
+// the pagination block of list responses
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Pagination {
+    pub total_records: u64,
+    pub total_pages: u64,
+    pub current_page: u64,
+}
+
+// a response without a pagination block is a single page
+impl Default for Pagination {
+    fn default() -> Pagination {
+        Pagination {
+            total_records: 0,
+            total_pages: 1,
+            current_page: 1,
+        }
+    }
+}
+
+// a linked record, epics carry the same fields as features
+#[derive(Debug, Clone)]
+pub enum Record {
+    Feature(Feature),
+    Requirement(Requirement),
+    Epic(Feature),
+}
+
+impl Record {
+    pub fn from_value(kind: &str, value: Value) -> Result<Record, serde_json::Error> {
+        Ok(match kind {
+            "requirement" => Record::Requirement(serde_json::from_value(value)?),
+            "epic" => Record::Epic(serde_json::from_value(value)?),
+            _ => Record::Feature(serde_json::from_value(value)?),
+        })
+    }
+
+    pub fn reference_num(&self) -> &str {
+        match self {
+            Record::Feature(f) | Record::Epic(f) => &f.reference_num,
+            Record::Requirement(r) => &r.reference_num,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Record::Feature(f) | Record::Epic(f) => &f.name,
+            Record::Requirement(r) => &r.name,
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        match self {
+            Record::Feature(f) | Record::Epic(f) => &f.url,
+            Record::Requirement(r) => &r.url,
+        }
+    }
+
+    pub fn workflow_status(&self) -> &WorkflowStatus {
+        match self {
+            Record::Feature(f) | Record::Epic(f) => &f.workflow_status,
+            Record::Requirement(r) => &r.workflow_status,
+        }
+    }
+
+    pub fn assigned_to_user(&self) -> Option<&User> {
+        match self {
+            Record::Feature(f) | Record::Epic(f) => f.assigned_to_user.as_ref(),
+            Record::Requirement(r) => r.assigned_to_user.as_ref(),
+        }
+    }
+
+    pub fn custom_fields(&self) -> &[CustomField] {
+        match self {
+            Record::Feature(f) | Record::Epic(f) => &f.custom_fields,
+            Record::Requirement(r) => &r.custom_fields,
+        }
+    }
+
+    pub fn description(&self) -> &str {
+        let description = match self {
+            Record::Feature(f) | Record::Epic(f) => f.description.as_ref(),
+            Record::Requirement(r) => r.description.as_ref(),
+        };
+        description.map(|d| d.body.as_str()).unwrap_or("")
+    }
+
+    // requirements take the release of their feature
+    pub fn release(&self) -> Option<&Release> {
+        match self {
+            Record::Feature(f) | Record::Epic(f) => f.release.as_ref(),
+            Record::Requirement(r) => r.feature.as_ref()?.release.as_ref(),
+        }
+    }
+
+    pub fn product_id(&self) -> Option<&str> {
+        let project = match self {
+            Record::Feature(f) | Record::Epic(f) => f.project.as_ref(),
+            Record::Requirement(_) => None,
+        };
+        self.release()
+            .and_then(|release| release.project.as_ref())
+            .or(project)
+            .map(|project| project.id.as_str())
+            .filter(|id| !id.is_empty())
+    }
+}
+
+// the labels that give a status with their rank in priority and the status,
+// best first. labels priority does not list rank after it, in name order
+fn label_matches<'l>(
+    labels: &'l [String],
+    label_statuses: &HashMap<String, String>,
+    priority: &[String],
+) -> Vec<(usize, &'l String, String)> {
+    let mut matches: Vec<(usize, &String, String)> = labels
+        .iter()
+        .filter_map(|label| {
+            let status = label_statuses.get(label)?;
+            let rank = priority
+                .iter()
+                .position(|p| p == label)
+                .unwrap_or(priority.len());
+            Some((rank, label, status.clone()))
+        })
+        .collect();
+    matches.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.cmp(b.1)));
+    matches
+}
+
+// status -> label from label -> status. of several labels for one status
+// the best ranked in priority, then by name, is used and configured wins
+fn inverse_labels(
+    label_statuses: HashMap<String, String>,
+    priority: &[String],
+    configured: Option<&HashMap<String, String>>,
+) -> HashMap<String, String> {
+    let mut labels: Vec<(String, String)> = label_statuses.into_iter().collect();
+    labels.sort_by_key(|(label, _)| {
+        let rank = priority
+            .iter()
+            .position(|p| p == label)
+            .unwrap_or(priority.len());
+        (rank, label.clone())
+    });
+    let mut status_labels = HashMap::new();
+    for (label, status) in labels {
+        status_labels.entry(status).or_insert(label);
+    }
+    if let Some(configured) = configured {
+        status_labels.extend(configured.clone());
+    }
+    status_labels
+}
+
+// whether an open pull request's record moved in aha since both sides last
+// agreed, so the labels follow aha instead of aha following the labels
+fn aha_moved(label_sync: bool, state: &str, last: Option<&str>, status: &str) -> bool {
+    label_sync && state == "OPEN" && last.is_some_and(|last| last != status)
+}
+
+// whether target comes before current in the order, None when either is not
+// in it
+fn backwards(order: &[String], current: &str, target: &str) -> Option<bool> {
+    let from = order.iter().position(|status| status == current)?;
+    let to = order.iter().position(|status| status == target)?;
+    Some(to < from)
+}
+
+// whether a closed pull request moves the record back to target. only ever a
+// step back, and never from a finished status or one at or past merged. the
+// reason is reported when it can not tell or will not.
+fn should_revert(
+    order: Option<&[String]>,
+    current: &WorkflowStatus,
+    target: &str,
+    merged: &str,
+) -> Result<bool, String> {
+    if current.complete {
+        return Err(format!("{} is complete", current.name));
+    }
+    let order = order
+        .ok_or_else(|| "no known workflow order, set workflow_order under [aha]".to_string())?;
+    let moves_back = backwards(order, &current.name, target).ok_or_else(|| {
+        format!(
+            "{} or {} is not in the workflow order",
+            current.name, target
+        )
+    })?;
+    let at = order.iter().position(|status| *status == current.name);
+    let shipped = order.iter().position(|status| status == merged);
+    if let (Some(at), Some(shipped)) = (at, shipped) {
+        if at >= shipped {
+            return Err(format!("{} is at or past {}", current.name, merged));
+        }
+    }
+    Ok(moves_back)
+}
+
+// the known status fewest edits away, ignoring case
+fn closest<'a>(status: &str, known: &'a [String]) -> &'a str {
+    let status = status.to_lowercase();
+    known
+        .iter()
+        .min_by_key(|name| edit_distance(&status, &name.to_lowercase()))
+        .map(|name| name.as_str())
+        .unwrap_or("")
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitute = previous[j] + if ca == *cb { 0 } else { 1 };
+            current.push(substitute.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+// how products, releases and features show up in the picker and match the
+// --product and --release flags
+pub trait Pick {
+    fn matches(&self, given: &str) -> bool;
+    fn choice(&self) -> picker::Choice;
+}
+
+impl Pick for Product {
+    fn matches(&self, given: &str) -> bool {
+        self.id == given || self.reference_prefix == given || self.name.eq_ignore_ascii_case(given)
+    }
+
+    fn choice(&self) -> picker::Choice {
+        picker::Choice {
+            label: self.name.clone(),
+            preview: format!("id {}, prefix {}", self.id, self.reference_prefix),
+        }
+    }
+}
+
+impl Pick for Release {
+    fn matches(&self, given: &str) -> bool {
+        self.id == given || self.reference_num == given || self.name.eq_ignore_ascii_case(given)
+    }
+
+    fn choice(&self) -> picker::Choice {
+        picker::Choice {
+            label: self.name.clone(),
+            preview: format!(
+                "id {}, release date {}",
+                self.id,
+                self.release_date.as_deref().unwrap_or("none")
+            ),
+        }
+    }
+}
+
+impl Pick for Feature {
+    fn matches(&self, given: &str) -> bool {
+        self.id == given || self.reference_num == given || self.name.eq_ignore_ascii_case(given)
+    }
+
+    fn choice(&self) -> picker::Choice {
+        picker::Choice {
+            label: format!("{} {}", self.reference_num, self.name),
+            preview: format!("id {}, status {}", self.id, self.workflow_status.name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn statuses(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn edit_distance_counts_single_character_edits() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("review", "review"), 0);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("in code reveiw", "in code review"), 2);
+    }
+
+    #[test]
+    fn closest_ignores_case_and_picks_the_fewest_edits() {
+        let known = statuses(&["In development", "In code review", "Ready to ship"]);
+        assert_eq!(closest("In Code Review", &known), "In code review");
+        assert_eq!(closest("Ready to shop", &known), "Ready to ship");
+        assert_eq!(closest("In develpment", &known), "In development");
+        assert_eq!(closest("anything", &[]), "");
+    }
+
+    fn label_map(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(label, status)| (label.to_string(), status.to_string()))
+            .collect()
+    }
+
+    fn label_statuses() -> HashMap<String, String> {
+        label_map(&[
+            ("In development", "In development"),
+            ("Needs code review", "In code review"),
+            ("Ready", "Ready to ship"),
+            ("Blocked", "On hold"),
+            ("Awaiting QA", "In QA"),
+        ])
+    }
+
+    #[test]
+    fn label_matches_skips_labels_without_a_status() {
+        let labels = statuses(&["bug", "Needs code review", "dependencies"]);
+        let priority = statuses(&["Ready", "Needs code review"]);
+        let matches = label_matches(&labels, &label_statuses(), &priority);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].0, 1);
+        assert_eq!(matches[0].1, "Needs code review");
+        assert_eq!(matches[0].2, "In code review");
+
+        let labels = statuses(&["bug"]);
+        assert!(label_matches(&labels, &label_statuses(), &priority).is_empty());
+    }
+
+    #[test]
+    fn label_matches_orders_by_priority() {
+        let labels = statuses(&["In development", "Needs code review", "Ready"]);
+        let priority = statuses(&["Ready", "Needs code review", "In development"]);
+        let matches = label_matches(&labels, &label_statuses(), &priority);
+        let order: Vec<&str> = matches.iter().map(|m| m.1.as_str()).collect();
+        assert_eq!(order, vec!["Ready", "Needs code review", "In development"]);
+        assert_eq!(matches[0].2, "Ready to ship");
+    }
+
+    #[test]
+    fn label_matches_puts_unlisted_labels_last_by_name() {
+        let labels = statuses(&["Blocked", "In development", "Awaiting QA"]);
+        let priority = statuses(&["In development"]);
+        let matches = label_matches(&labels, &label_statuses(), &priority);
+        let order: Vec<(usize, &str)> = matches.iter().map(|m| (m.0, m.1.as_str())).collect();
+        assert_eq!(
+            order,
+            vec![(0, "In development"), (1, "Awaiting QA"), (1, "Blocked")]
+        );
+    }
+
+    #[test]
+    fn inverse_labels_breaks_ties_by_priority_then_name() {
+        let label_statuses = label_map(&[
+            ("Ready", "Ready to ship"),
+            ("LGTM", "Ready to ship"),
+            ("Approved", "Ready to ship"),
+            ("Needs code review", "In code review"),
+            ("Review please", "In code review"),
+        ]);
+        let priority = statuses(&["LGTM", "Ready"]);
+        let inverse = inverse_labels(label_statuses, &priority, None);
+        assert_eq!(inverse["Ready to ship"], "LGTM");
+        assert_eq!(inverse["In code review"], "Needs code review");
+        assert_eq!(inverse.len(), 2);
+    }
+
+    #[test]
+    fn inverse_labels_lets_the_repo_override() {
+        let configured = label_map(&[("Ready to ship", "Ready"), ("In QA", "qa")]);
+        let inverse = inverse_labels(
+            label_map(&[("Ready", "Ready to ship"), ("LGTM", "Ready to ship")]),
+            &statuses(&["LGTM"]),
+            Some(&configured),
+        );
+        assert_eq!(inverse["Ready to ship"], "Ready");
+        assert_eq!(inverse["In QA"], "qa");
+    }
+
+    #[test]
+    fn aha_moved_only_for_a_changed_open_record() {
+        assert!(aha_moved(
+            true,
+            "OPEN",
+            Some("In code review"),
+            "In PM review"
+        ));
+        // never synced, or aha still where both sides agreed
+        assert!(!aha_moved(true, "OPEN", None, "In PM review"));
+        assert!(!aha_moved(
+            true,
+            "OPEN",
+            Some("In PM review"),
+            "In PM review"
+        ));
+        // merged pull requests still apply their status, labels off never follow
+        assert!(!aha_moved(
+            true,
+            "MERGED",
+            Some("In code review"),
+            "In PM review"
+        ));
+        assert!(!aha_moved(
+            false,
+            "OPEN",
+            Some("In code review"),
+            "In PM review"
+        ));
+    }
+
+    fn status(name: &str, complete: bool) -> WorkflowStatus {
+        WorkflowStatus {
+            name: name.to_string(),
+            complete,
+            ..WorkflowStatus::default()
+        }
+    }
+
+    #[test]
+    fn should_revert_only_steps_back_from_work_in_progress() {
+        let order = statuses(&[
+            "Ready to develop",
+            "In development",
+            "In code review",
+            "Ready to ship",
+            "Shipped",
+        ]);
+        let revert = |current: &WorkflowStatus| {
+            should_revert(Some(&order), current, "Ready to develop", "Ready to ship")
+        };
+        assert_eq!(revert(&status("In code review", false)), Ok(true));
+        assert_eq!(revert(&status("Ready to develop", false)), Ok(false));
+        assert!(revert(&status("Ready to ship", false)).is_err());
+        assert!(revert(&status("Shipped", true)).is_err());
+        assert!(revert(&status("Unknown", false)).is_err());
+        assert!(should_revert(
+            None,
+            &status("In code review", false),
+            "Ready to develop",
+            "Ready to ship"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn backwards_needs_both_statuses_in_the_order() {
+        let order = statuses(&["Ready to develop", "In development", "Shipped"]);
+        assert_eq!(backwards(&order, "Shipped", "Ready to develop"), Some(true));
+        assert_eq!(
+            backwards(&order, "Ready to develop", "Shipped"),
+            Some(false)
+        );
+        assert_eq!(backwards(&order, "Shipped", "Done"), None);
+    }
+
+    #[test]
+    fn pagination_defaults_to_a_single_page() {
+        let missing: Option<Pagination> = decode("url", Value::Null).unwrap();
+        assert_eq!(missing.unwrap_or_default().total_pages, 1);
+
+        let partial: Option<Pagination> =
+            decode("url", serde_json::json!({ "total_records": 3 })).unwrap();
+        let partial = partial.unwrap_or_default();
+        assert_eq!((partial.total_records, partial.total_pages), (3, 1));
+
+        let full: Option<Pagination> = decode(
+            "url",
+            serde_json::json!({ "total_records": 450, "total_pages": 3, "current_page": 1 }),
+        )
+        .unwrap();
+        assert_eq!(full.unwrap_or_default().total_pages, 3);
+    }
+}
@@ -0,0 +1,209 @@
+use super::Opt;
+use std::process::Command;
+
+pub const DEFAULT_BRANCH_TEMPLATE: &str = "{reference}-{name}";
+pub const DEFAULT_BRANCH_LENGTH: usize = 60;
+const REMOTE: &str = "origin";
+
+// {reference} and {name} are filled in, the name is slugged and cut on a dash
+// so the branch fits in max_length. a name starting with a digit gets an
+// underscore first, ENG-12-3-step would read as the requirement ENG-12-3.
+pub fn branch_name(template: &str, reference: &str, name: &str, max_length: usize) -> String {
+    let reference = slug(reference, false);
+    let mut name = slug(name, true);
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        name.insert(0, '_');
+    }
+    loop {
+        let branch = template
+            .replace("{reference}", &reference)
+            .replace("{name}", &name);
+        let branch = branch.trim_matches(['-', '/']).to_string();
+        if branch.len() <= max_length || name.is_empty() {
+            return branch;
+        }
+        name = match name.rfind('-') {
+            Some(index) => name[..index].to_string(),
+            None => String::new(),
+        };
+    }
+}
+
+// anything but ascii letters and digits becomes a single dash
+fn slug(text: &str, lowercase: bool) -> String {
+    let mut slug = String::new();
+    for c in text.chars() {
+        if c.is_ascii_alphanumeric() {
+            if lowercase {
+                slug.push(c.to_ascii_lowercase());
+            } else {
+                slug.push(c);
+            }
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+// a repository with a default branch to start from, checked before anything
+// is made or moved in aha. returns the default branch.
+pub fn check_repository(opt: &Opt) -> Result<String, failure::Error> {
+    git(&["rev-parse", "--show-toplevel"], opt, false)
+        .map_err(|e| format_err!("not in a git repository: {}", e))?;
+    default_branch(opt)
+}
+
+pub fn check_branch(branch: &str, opt: &Opt) -> Result<(), failure::Error> {
+    if !git(&["branch", "--list", branch], opt, false)?.is_empty() {
+        return Err(format_err!("branch {} already exists", branch));
+    }
+    Ok(())
+}
+
+// fetch the default branch, stash local changes and start the new branch on
+// it. the stash comes last so a failed fetch leaves the changes in place.
+pub fn start_branch(branch: &str, default: &str, opt: &Opt) -> Result<(), failure::Error> {
+    check_branch(branch, opt)?;
+    git(&["fetch", REMOTE, default], opt, true)?;
+    if !git(&["status", "--porcelain"], opt, false)?.is_empty() {
+        let message = format!("rust-workflow: before {}", branch);
+        git(
+            &["stash", "push", "--include-untracked", "-m", &message],
+            opt,
+            true,
+        )?;
+    }
+    let start = format!("{}/{}", REMOTE, default);
+    git(&["checkout", "--no-track", "-b", branch, &start], opt, true)?;
+    Ok(())
+}
+
+// the feature a branch was started for, kept in the repository's config so
+// --finish does not have to read it back out of the name
+pub fn record_reference(branch: &str, reference: &str, opt: &Opt) -> Result<(), failure::Error> {
+    let key = format!("branch.{}.aha-reference", branch);
+    git(&["config", &key, reference], opt, true)?;
+    Ok(())
+}
+
+pub fn recorded_reference(branch: &str, opt: &Opt) -> Option<String> {
+    let key = format!("branch.{}.aha-reference", branch);
+    git(&["config", "--get", &key], opt, false)
+        .ok()
+        .filter(|reference| !reference.is_empty())
+}
+
+pub fn current_branch(opt: &Opt) -> Result<String, failure::Error> {
+    git(&["rev-parse", "--abbrev-ref", "HEAD"], opt, false)
+}
+
+// owner/name from the remote url, ie git@github.com:owner/name.git
+pub fn remote_repo(opt: &Opt) -> Result<String, failure::Error> {
+    let url = git(&["remote", "get-url", REMOTE], opt, false)?;
+    let path = url.trim_end_matches('/').trim_end_matches(".git");
+    let mut parts = path.rsplit(['/', ':']);
+    match (parts.next(), parts.next()) {
+        (Some(name), Some(owner)) if !name.is_empty() && !owner.is_empty() => {
+            Ok(format!("{}/{}", owner, name))
+        }
+        _ => Err(format_err!("could not find the repository in {}", url)),
+    }
+}
+
+pub fn push_branch(branch: &str, opt: &Opt) -> Result<(), failure::Error> {
+    git(&["push", "--set-upstream", REMOTE, branch], opt, true)?;
+    Ok(())
+}
+
+// origin/HEAD when it is set, otherwise ask the remote
+pub fn default_branch(opt: &Opt) -> Result<String, failure::Error> {
+    let head = format!("refs/remotes/{}/HEAD", REMOTE);
+    if let Ok(head) = git(&["symbolic-ref", "--short", &head], opt, false) {
+        if let Some(branch) = head.strip_prefix(&format!("{}/", REMOTE)) {
+            return Ok(branch.to_string());
+        }
+    }
+    let remote = git(&["remote", "show", REMOTE], opt, false)?;
+    remote
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("HEAD branch:"))
+        .map(|branch| branch.trim().to_string())
+        .filter(|branch| branch != "(unknown)")
+        .ok_or_else(|| format_err!("could not find the default branch of {}", REMOTE))
+}
+
+// changes is false for read only commands, those still run on a dry run
+fn git(args: &[&str], opt: &Opt, changes: bool) -> Result<String, failure::Error> {
+    if opt.verbose || (changes && opt.dry_run) {
+        println!("git {}", args.join(" "));
+    }
+    if changes && opt.dry_run {
+        return Ok(String::new());
+    }
+    let output = Command::new("git").args(args).output()?;
+    if !output.status.success() {
+        return Err(format_err!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reference::ReferencePatterns;
+
+    #[test]
+    fn branch_name_fills_the_template() {
+        assert_eq!(
+            branch_name(DEFAULT_BRANCH_TEMPLATE, "ENG-12", "Fix the login page!", 60),
+            "ENG-12-fix-the-login-page"
+        );
+        assert_eq!(
+            branch_name("feature/{reference}/{name}", "ENG-12", "Login", 60),
+            "feature/ENG-12/login"
+        );
+    }
+
+    #[test]
+    fn branch_name_cuts_the_name_on_a_dash() {
+        assert_eq!(
+            branch_name(DEFAULT_BRANCH_TEMPLATE, "ENG-12", "fix the login page", 20),
+            "ENG-12-fix-the-login"
+        );
+        assert_eq!(
+            branch_name(
+                DEFAULT_BRANCH_TEMPLATE,
+                "ENG-12",
+                "internationalization",
+                10
+            ),
+            "ENG-12"
+        );
+    }
+
+    #[test]
+    fn branch_name_reads_back_as_the_feature() {
+        let branch = branch_name(DEFAULT_BRANCH_TEMPLATE, "ENG-12", "3 step checkout", 60);
+        assert_eq!(branch, "ENG-12-_3-step-checkout");
+        let feature = vec![("feature".to_string(), "ENG-12".to_string())];
+        assert_eq!(ReferencePatterns::default().find(&branch), feature);
+        let products = ReferencePatterns::for_products(&["ENG".to_string()]);
+        assert_eq!(products.find(&branch), feature);
+    }
+
+    #[test]
+    fn slug_collapses_everything_but_letters_and_digits() {
+        assert_eq!(
+            slug("  Fix: the  *login* page  ", true),
+            "fix-the-login-page"
+        );
+        assert_eq!(slug("ENG-12", false), "ENG-12");
+        assert_eq!(slug("Café au lait", true), "caf-au-lait");
+        assert_eq!(slug("!!!", true), "");
+    }
+}
@@ -1,18 +1,132 @@
+use super::retry::{Failure, Retry};
+use graphql_client::{GraphQLQuery, Response};
+use repo_view::PullRequestFieldsReviewRequestsNodesRequestedReviewer as RequestedReviewer;
+use std::collections::HashMap;
+use std::time::Duration;
+
+#[allow(clippy::upper_case_acronyms)]
+type URI = String;
+type DateTime = String;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/schema.json",
+    query_path = "src/query_1.graphql",
+    variables_derives = "Clone",
+    response_derives = "Debug"
+)]
+pub struct RepoView;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/schema.json",
+    query_path = "src/query_1.graphql",
+    response_derives = "Debug"
+)]
+pub struct PullView;
+
+pub const DEFAULT_API_URL: &str = "https://api.github.com";
+
 #[derive(Debug, Clone)]
 pub struct GithubEnv {
+    pub client: reqwest::Client,
     pub github_api_token: String,
+    pub api_url: String,
     pub workflow_repo: String,
     pub workflow_login: String,
     pub silent: bool,
     pub verbose: bool,
 }
 
+impl GithubEnv {
+    // enterprise serves rest from /api/v3 and graphql from /api/graphql
+    pub fn graphql_url(&self) -> String {
+        let base = self.api_url.trim_end_matches('/');
+        let base = if base.ends_with("/api/v3") {
+            base.trim_end_matches("/v3")
+        } else {
+            base
+        };
+        format!("{}/graphql", base)
+    }
+
+    pub fn rest_url(&self, path: &str) -> String {
+        format!("{}/{}", self.api_url.trim_end_matches('/'), path)
+    }
+
+    // retries 5xx and rate limited responses, anything else non 2xx is an error
+    pub fn send<F>(&self, build: F) -> Result<reqwest::Response, failure::Error>
+    where
+        F: Fn() -> reqwest::RequestBuilder,
+    {
+        self.send_with(build, true)
+    }
+
+    // for posts that create something. a lost response may still have
+    // created it, so only a failed connect or a rate limit is retried
+    pub fn send_create<F>(&self, build: F) -> Result<reqwest::Response, failure::Error>
+    where
+        F: Fn() -> reqwest::RequestBuilder,
+    {
+        self.send_with(build, false)
+    }
+
+    fn send_with<F>(&self, build: F, repeatable: bool) -> Result<reqwest::Response, failure::Error>
+    where
+        F: Fn() -> reqwest::RequestBuilder,
+    {
+        let retry = Retry {
+            service: "github",
+            silent: self.silent,
+            limiter: None,
+        };
+        let build = || build().bearer_auth(self.github_api_token.clone());
+        match retry.send(build, repeatable) {
+            Ok(res) => Ok(res),
+            Err(Failure::Transport(error)) => Err(error.into()),
+            Err(Failure::Status {
+                status,
+                url,
+                text,
+                attempts,
+            }) => Err(format_err!(
+                "github {} failed with {} after {} attempts: {}",
+                url,
+                status,
+                attempts,
+                text
+            )),
+        }
+    }
+}
+
+pub fn client() -> Result<reqwest::Client, failure::Error> {
+    let mut headers = reqwest::header::HeaderMap::new();
+    headers.insert(
+        reqwest::header::USER_AGENT,
+        reqwest::header::HeaderValue::from_static("rust-workflow"),
+    );
+    let client = reqwest::Client::builder()
+        .default_headers(headers)
+        .timeout(Duration::from_secs(50))
+        .build()?;
+    Ok(client)
+}
+
 #[derive(Debug)]
 pub struct PullRequest {
     pub number: i64,
     pub name: String,
     pub url: String,
     pub labels: Vec<String>,
+    pub head_ref: String,
+    pub body: String,
+    pub commits: Vec<String>,
+    pub state: String,
+    pub draft: bool,
+    pub approvals: usize,
+    pub review_decision: Option<String>,
+    pub checks: Option<String>,
 }
 
 fn parse_repo_name(repo_name: &str) -> Result<(&str, &str), failure::Error> {
@@ -26,7 +140,6 @@ fn parse_repo_name(repo_name: &str) -> Result<(&str, &str), failure::Error> {
 pub fn pr_table(response_body: &RootInterface) {
     let mut table = prettytable::Table::new();
     for issue in response_body.items.iter() {
-        let ref_head = issue.title.clone();
         let label_names: Vec<String> = issue
             .labels
             .iter()
@@ -42,7 +155,6 @@ pub fn pr_table(response_body: &RootInterface) {
         );
 
         body.truncate(20);
-        // let requested_reviewers: Vec<String> = issue .requested_reviewers .iter() .map(|user| user.login.clone()) .collect();
 
         let mergeable = match &issue.mergeable {
             Some(mer) => mer.clone(),
@@ -53,133 +165,651 @@ pub fn pr_table(response_body: &RootInterface) {
             Some(mer) => mer.clone(),
             _ => "".to_string(),
         };
+
+        let review_decision = match &issue.review_decision {
+            Some(decision) => decision.clone(),
+            _ => "".to_string(),
+        };
         table.add_row(row!(
             issue.title,
+            issue.state,
             body,
-            ref_head,
+            issue.ref_head,
             label_names.join(","),
             issue.html_url,
             checked,
+            issue.draft,
             mergeable,
             mergeable_state,
-            //requested_reviewers.join(",")
+            issue.requested_reviewers.join(","),
+            review_decision
         ));
     }
     table.printstd();
 }
 
-pub fn pr_data(config: &GithubEnv, author: String, open: bool) -> RootInterface {
-    let (owner, name) = parse_repo_name(&config.workflow_repo).unwrap();
-    let client = reqwest::Client::new();
-    let state = if open { "open" } else { "closed" };
-    let author = if author.len() > 0 {
-        format!("+author:{}", author)
+// since limits closed pull requests to ones closed after it, results come
+// back most recently updated first so paging stops once they are older
+pub fn pr_data(
+    config: &GithubEnv,
+    author: String,
+    open: bool,
+    since: Option<String>,
+) -> Result<RootInterface, failure::Error> {
+    let (owner, name) = parse_repo_name(&config.workflow_repo)?;
+    let states = if open {
+        vec![repo_view::PullRequestState::OPEN]
     } else {
-        author
+        vec![
+            repo_view::PullRequestState::CLOSED,
+            repo_view::PullRequestState::MERGED,
+        ]
     };
-    let url = format!(
-        "https://api.github.com/search/issues?q=is:{}+is:pr+repo:{}/{}{}&sort=created",
-        state, owner, name, author
-    );
+    let url = config.graphql_url();
+    let mut after = None;
+    let mut total_count;
+    let mut fetched = 0;
+    let mut stopped = false;
+    let mut items = Vec::new();
+    loop {
+        let query = RepoView::build_query(repo_view::Variables {
+            owner: owner.to_string(),
+            name: name.to_string(),
+            states: Some(states.clone()),
+            after: after.clone(),
+        });
+        if config.verbose {
+            println!(
+                "github graphql url: {} {}/{} after {:?}",
+                url, owner, name, after
+            )
+        }
+        let mut res = config.send(|| config.client.post(&url).json(&query))?;
+
+        let response: Response<repo_view::ResponseData> = res.json()?;
+        graphql_errors(response.errors)?;
+        let pull_requests = response
+            .data
+            .and_then(|data| data.repository)
+            .map(|repository| repository.pull_requests)
+            .ok_or_else(|| format_err!("Could not find repo {}/{}", owner, name))?;
+
+        total_count = pull_requests.total_count;
+        let nodes: Vec<_> = pull_requests
+            .nodes
+            .unwrap_or_default()
+            .into_iter()
+            .flatten()
+            .collect();
+        fetched += nodes.len() as i64;
+        if let (Some(since), Some(last)) = (&since, nodes.last()) {
+            stopped = last.updated_at < *since;
+        }
+        items.extend(
+            nodes
+                .into_iter()
+                .map(Items::from)
+                .filter(|item| author.is_empty() || item.author == author)
+                .filter(|item| match (&since, &item.closed_at) {
+                    (Some(since), Some(closed_at)) => closed_at >= since,
+                    (Some(_), None) => false,
+                    _ => true,
+                }),
+        );
+
+        let page_info = pull_requests.page_info;
+        match page_info.end_cursor {
+            Some(cursor) if page_info.has_next_page && !stopped => after = Some(cursor),
+            _ => break,
+        }
+    }
+
+    // draft, mergeable_state and check runs are only on the rest endpoints
+    for item in items.iter_mut() {
+        let detail = pr_detail(config, owner, name, item.number)?;
+        item.draft = detail.draft.unwrap_or(false);
+        let runs = check_runs(config, owner, name, &detail);
+        item.checks = combined_checks(item.checks.take(), &runs);
+        item.mergeable_state = detail.mergeable_state;
+    }
+
+    // github can drop nodes it fails to load, the count still includes them
+    if fetched < total_count && !stopped && !config.silent {
+        println!(
+            "warning: github returned {} of {} pull requests for {}/{}, results are incomplete",
+            fetched, total_count, owner, name
+        );
+    }
+    Ok(RootInterface { total_count, items })
+}
+
+fn pr_detail(
+    config: &GithubEnv,
+    owner: &str,
+    name: &str,
+    number: i64,
+) -> Result<PullDetail, failure::Error> {
+    let url = config.rest_url(&format!("repos/{}/{}/pulls/{}", owner, name, number));
+    if config.verbose {
+        println!("github pull url: {}", url)
+    }
+    let mut res = config.send(|| config.client.get(&url))?;
+    Ok(res.json()?)
+}
+
+// the head commit's check runs, which is where github actions reports. a token
+// that can not read them leaves the legacy statuses to decide.
+fn check_runs(config: &GithubEnv, owner: &str, name: &str, detail: &PullDetail) -> Vec<CheckRun> {
+    let sha = match &detail.head {
+        Some(head) => &head.sha,
+        None => return vec![],
+    };
+    let url = config.rest_url(&format!(
+        "repos/{}/{}/commits/{}/check-runs?per_page=100",
+        owner, name, sha
+    ));
     if config.verbose {
-        println!("github search url: {}", url)
+        println!("github check runs url: {}", url)
+    }
+    let runs = config
+        .send(|| config.client.get(&url))
+        .and_then(|mut res| Ok(res.json::<CheckRuns>()?));
+    match runs {
+        Ok(runs) => runs.check_runs,
+        Err(error) => {
+            if !config.silent {
+                println!("warning: could not read check runs for {}: {}", sha, error);
+            }
+            vec![]
+        }
     }
-    let mut res = client
-        .get(&url)
-        .basic_auth(
-            config.workflow_login.clone(),
-            Some(config.github_api_token.clone()),
-        )
-        .send()
-        .unwrap();
+}
 
-    res.json().expect("Could not find repo")
+// the commit status and the check runs as one state, the worst wins: failure
+// or error, then pending or expected, then success
+fn combined_checks(status: Option<String>, runs: &[CheckRun]) -> Option<String> {
+    status
+        .into_iter()
+        .chain(runs.iter().map(CheckRun::state))
+        .max_by_key(|state| match state.as_str() {
+            "FAILURE" | "ERROR" => 2,
+            "PENDING" | "EXPECTED" => 1,
+            _ => 0,
+        })
 }
 
 pub fn prs(config: GithubEnv) -> Result<Vec<PullRequest>, failure::Error> {
-    let response_body = pr_data(&config, config.workflow_login.clone(), true);
+    pull_requests(&config, true, None)
+}
+
+// merged and closed pull requests by the configured user from the last days
+pub fn closed_prs(config: GithubEnv, days: i64) -> Result<Vec<PullRequest>, failure::Error> {
+    pull_requests(&config, false, Some(closed_since(days)))
+}
+
+// the oldest update a closed pull request can have and still be listed
+pub fn closed_since(days: i64) -> String {
+    let since = chrono::Utc::now() - chrono::Duration::days(days);
+    since.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+}
+
+fn pull_requests(
+    config: &GithubEnv,
+    open: bool,
+    since: Option<String>,
+) -> Result<Vec<PullRequest>, failure::Error> {
+    let response_body = pr_data(config, config.workflow_login.clone(), open, since)?;
     if config.verbose {
+        println!(
+            "{} of {} pull requests by {}",
+            response_body.items.len(),
+            response_body.total_count,
+            config.workflow_login
+        );
         pr_table(&response_body);
     }
     let response_data = response_body.items;
     let mut branches: Vec<PullRequest> = Vec::new();
     for issue in &response_data {
-        let ref_head = issue.title.clone();
+        branches.push(PullRequest::from(issue));
+    }
+
+    Ok(branches)
+}
+
+// a single pull request with the same facts pr_data lists
+pub fn pull_request(config: &GithubEnv, number: i64) -> Result<PullRequest, failure::Error> {
+    let (owner, name) = parse_repo_name(&config.workflow_repo)?;
+    let query = PullView::build_query(pull_view::Variables {
+        owner: owner.to_string(),
+        name: name.to_string(),
+        number,
+    });
+    let url = config.graphql_url();
+    if config.verbose {
+        println!("github graphql url: {} {}/{} #{}", url, owner, name, number)
+    }
+    let mut res = config.send(|| config.client.post(&url).json(&query))?;
+
+    // both queries select PullRequestFields so the node reads the same way
+    let response: Response<serde_json::Value> = res.json()?;
+    graphql_errors(response.errors)?;
+    let node = response
+        .data
+        .map(|mut data| data["repository"]["pullRequest"].take())
+        .filter(|node| !node.is_null())
+        .ok_or_else(|| format_err!("Could not find {}/{}#{}", owner, name, number))?;
+    let node: repo_view::PullRequestFields = serde_json::from_value(node)?;
+
+    let mut item = Items::from(node);
+    let detail = pr_detail(config, owner, name, number)?;
+    item.draft = detail.draft.unwrap_or(false);
+    let runs = check_runs(config, owner, name, &detail);
+    item.checks = combined_checks(item.checks.take(), &runs);
+    item.mergeable_state = detail.mergeable_state;
+    Ok(PullRequest::from(&item))
+}
+
+#[derive(Debug, Serialize)]
+pub struct NewPullRequest {
+    pub title: String,
+    pub head: String,
+    pub base: String,
+    pub body: String,
+    pub draft: bool,
+}
+
+// opens the pull request and returns its number
+pub fn create_pull_request(
+    config: &GithubEnv,
+    new_pr: &NewPullRequest,
+) -> Result<i64, failure::Error> {
+    let (owner, name) = parse_repo_name(&config.workflow_repo)?;
+    let url = config.rest_url(&format!("repos/{}/{}/pulls", owner, name));
+    if config.verbose {
+        println!("github create pull: {} {:?}", url, new_pr);
+    }
+    let mut res = config.send_create(|| config.client.post(&url).json(new_pr))?;
+    let created: serde_json::Value = res.json()?;
+    created["number"]
+        .as_i64()
+        .ok_or_else(|| format_err!("github did not return a pull request: {}", created))
+}
+
+const COMMENT_MARKER: &str = "<!-- rust-workflow:aha -->";
+
+// edits the comment carrying the marker, or adds it the first time
+pub fn upsert_comment(config: &GithubEnv, number: i64, body: &str) -> Result<(), failure::Error> {
+    let (owner, name) = parse_repo_name(&config.workflow_repo)?;
+    let body = format!("{}\n{}", COMMENT_MARKER, body);
+    let mut page = 1;
+    let existing = loop {
+        let url = config.rest_url(&format!(
+            "repos/{}/{}/issues/{}/comments?per_page=100&page={}",
+            owner, name, number, page
+        ));
+        let mut res = config.send(|| config.client.get(&url))?;
+        let comments: Vec<IssueComment> = res.json()?;
+        if let Some(comment) = comments.iter().find(|c| c.body.contains(COMMENT_MARKER)) {
+            break Some(comment.clone());
+        }
+        if comments.len() < 100 {
+            break None;
+        }
+        page += 1;
+    };
+    let payload = serde_json::json!({ "body": body });
+    match existing {
+        Some(comment) if comment.body == body => {
+            if config.verbose {
+                println!("comment {} on #{} is current", comment.id, number);
+            }
+        }
+        Some(comment) => {
+            let url = config.rest_url(&format!(
+                "repos/{}/{}/issues/comments/{}",
+                owner, name, comment.id
+            ));
+            config.send(|| config.client.patch(&url).json(&payload))?;
+        }
+        None => {
+            let url = config.rest_url(&format!(
+                "repos/{}/{}/issues/{}/comments",
+                owner, name, number
+            ));
+            config.send_create(|| config.client.post(&url).json(&payload))?;
+        }
+    }
+    Ok(())
+}
+
+pub fn update_labels(
+    config: &GithubEnv,
+    number: i64,
+    add: &[String],
+    remove: &[String],
+) -> Result<(), failure::Error> {
+    let (owner, name) = parse_repo_name(&config.workflow_repo)?;
+    let labels_url = config.rest_url(&format!(
+        "repos/{}/{}/issues/{}/labels",
+        owner, name, number
+    ));
+    for label in remove {
+        // label names can hold spaces and slashes
+        let mut url = url::Url::parse(&labels_url)?;
+        url.path_segments_mut()
+            .map_err(|_| format_err!("{} can not take a path", labels_url))?
+            .push(label);
+        config.send(|| config.client.delete(url.as_str()))?;
+    }
+    if !add.is_empty() {
+        let payload = serde_json::json!({ "labels": add });
+        config.send(|| config.client.post(&labels_url).json(&payload))?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Deserialize, Clone)]
+struct IssueComment {
+    id: i64,
+    body: String,
+}
+
+fn graphql_errors(errors: Option<Vec<graphql_client::Error>>) -> Result<(), failure::Error> {
+    match errors {
+        Some(errors) if !errors.is_empty() => {
+            let messages: Vec<String> = errors.iter().map(|error| error.message.clone()).collect();
+            Err(format_err!(
+                "github graphql errors: {}",
+                messages.join(", ")
+            ))
+        }
+        _ => Ok(()),
+    }
+}
+
+impl From<&Items> for PullRequest {
+    fn from(issue: &Items) -> PullRequest {
         let label_names: Vec<String> = issue
             .labels
             .iter()
             .map(|label| label.name.clone())
             .collect();
-        let pull = PullRequest {
+        PullRequest {
             number: issue.number,
             url: issue.html_url.clone(),
-            name: ref_head,
+            name: issue.title.clone(),
             labels: label_names,
-        };
-        branches.push(pull);
+            head_ref: issue.ref_head.clone(),
+            body: issue.body.clone(),
+            commits: issue.commits.clone(),
+            state: issue.state.clone(),
+            draft: issue.draft,
+            approvals: issue.approvals,
+            review_decision: issue.review_decision.clone(),
+            checks: issue.checks.clone(),
+        }
     }
-
-    Ok(branches)
 }
 
-#[derive(Serialize, Debug, Deserialize, Clone)]
+#[derive(Debug, Clone)]
 struct Items {
-    url: String,
     html_url: String,
-    id: i64,
-    node_id: String,
     number: i64,
     title: String,
+    author: String,
     labels: Vec<Labels>,
     state: String,
-    created_at: String,
-    updated_at: String,
     closed_at: Option<String>,
     body: String,
+    ref_head: String,
+    draft: bool,
     mergeable: Option<String>,
     mergeable_state: Option<String>,
-    requested_reviewers: Option<Vec<User>>,
+    requested_reviewers: Vec<String>,
+    review_decision: Option<String>,
+    approvals: usize,
+    commits: Vec<String>,
+    checks: Option<String>,
 }
 
-#[derive(Serialize, Debug, Deserialize, Clone)]
-struct Labels {
-    name: String,
+impl From<repo_view::PullRequestFields> for Items {
+    fn from(node: repo_view::PullRequestFields) -> Items {
+        let labels = node
+            .labels
+            .and_then(|labels| labels.edges)
+            .unwrap_or_default()
+            .into_iter()
+            .flatten()
+            .filter_map(|edge| edge.node)
+            .map(|label| Labels { name: label.name })
+            .collect();
+        let requested_reviewers: Vec<String> = node
+            .review_requests
+            .and_then(|requests| requests.nodes)
+            .unwrap_or_default()
+            .into_iter()
+            .flatten()
+            .filter_map(|request| request.requested_reviewer)
+            .map(|reviewer| match reviewer {
+                RequestedReviewer::User(user) => user.login,
+                RequestedReviewer::Team(team) => team.name,
+            })
+            .collect();
+        let reviews: Vec<(String, String)> = node
+            .reviews
+            .and_then(|reviews| reviews.nodes)
+            .unwrap_or_default()
+            .into_iter()
+            .flatten()
+            .map(|review| {
+                let login = review.author.map(|author| author.login).unwrap_or_default();
+                (login, format!("{:?}", review.state))
+            })
+            .collect();
+        let (review_decision, approvals) =
+            review_decision(&reviews, !requested_reviewers.is_empty());
+        let commits: Vec<_> = node
+            .commits
+            .nodes
+            .unwrap_or_default()
+            .into_iter()
+            .flatten()
+            .map(|commit| commit.commit)
+            .collect();
+        // the head commit's combined status, check runs are added from rest
+        let checks = commits
+            .last()
+            .and_then(|commit| commit.status.as_ref())
+            .map(|status| format!("{:?}", status.state));
+        let commits = commits.into_iter().map(|commit| commit.message).collect();
+        Items {
+            html_url: node.url,
+            number: node.number,
+            title: node.title,
+            author: node.author.map(|author| author.login).unwrap_or_default(),
+            labels,
+            state: format!("{:?}", node.state),
+            closed_at: node.closed_at,
+            body: node.body,
+            ref_head: node.head_ref_name,
+            draft: false,
+            mergeable: Some(format!("{:?}", node.mergeable)),
+            mergeable_state: None,
+            requested_reviewers,
+            review_decision,
+            approvals,
+            commits,
+            checks,
+        }
+    }
 }
 
-#[derive(Serialize, Debug, Deserialize)]
-struct PullRequest1 {
-    url: String,
-    html_url: String,
-    diff_url: String,
-    patch_url: String,
+// the schema we build against predates reviewDecision, so work it out from
+// each reviewer's latest review the same way github does
+fn review_decision(reviews: &[(String, String)], requested: bool) -> (Option<String>, usize) {
+    let mut latest: HashMap<&str, &str> = HashMap::new();
+    for (login, state) in reviews {
+        match state.as_str() {
+            "APPROVED" | "CHANGES_REQUESTED" => {
+                latest.insert(login, state);
+            }
+            "DISMISSED" => {
+                latest.remove(login.as_str());
+            }
+            _ => (),
+        }
+    }
+    let approvals = latest
+        .values()
+        .filter(|state| **state == "APPROVED")
+        .count();
+    let decision = if latest.values().any(|state| *state == "CHANGES_REQUESTED") {
+        Some("CHANGES_REQUESTED".to_string())
+    } else if approvals > 0 {
+        Some("APPROVED".to_string())
+    } else if requested {
+        Some("REVIEW_REQUIRED".to_string())
+    } else {
+        None
+    };
+    (decision, approvals)
 }
 
-#[derive(Serialize, Debug, Deserialize)]
+#[derive(Debug, Deserialize)]
+struct PullDetail {
+    draft: Option<bool>,
+    mergeable_state: Option<String>,
+    head: Option<PullHead>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PullHead {
+    sha: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CheckRuns {
+    check_runs: Vec<CheckRun>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CheckRun {
+    status: String,
+    conclusion: Option<String>,
+}
+
+impl CheckRun {
+    // in the commit status vocabulary
+    fn state(&self) -> String {
+        if self.status != "completed" {
+            return "PENDING".to_string();
+        }
+        match self.conclusion.as_deref() {
+            Some("success") | Some("neutral") | Some("skipped") => "SUCCESS".to_string(),
+            _ => "FAILURE".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Labels {
+    name: String,
+}
+
+#[derive(Debug)]
 pub struct RootInterface {
     total_count: i64,
-    incomplete_results: bool,
     items: Vec<Items>,
 }
 
-#[derive(Serialize, Debug, Deserialize, Clone)]
-struct User {
-    login: String,
-    id: i64,
-    node_id: String,
-    avatar_url: String,
-    gravatar_id: String,
-    url: String,
-    html_url: String,
-    followers_url: String,
-    following_url: String,
-    gists_url: String,
-    starred_url: String,
-    subscriptions_url: String,
-    organizations_url: String,
-    repos_url: String,
-    events_url: String,
-    received_events_url: String,
-    #[serde(rename = "type")]
-    _type: String,
-    site_admin: bool,
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reviews(reviews: &[(&str, &str)]) -> Vec<(String, String)> {
+        reviews
+            .iter()
+            .map(|(login, state)| (login.to_string(), state.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn review_decision_uses_each_reviewers_latest_review() {
+        let list = reviews(&[("ann", "CHANGES_REQUESTED"), ("ann", "APPROVED")]);
+        assert_eq!(
+            review_decision(&list, false),
+            (Some("APPROVED".to_string()), 1)
+        );
+        let list = reviews(&[("ann", "APPROVED"), ("ann", "CHANGES_REQUESTED")]);
+        assert_eq!(
+            review_decision(&list, false),
+            (Some("CHANGES_REQUESTED".to_string()), 0)
+        );
+    }
+
+    #[test]
+    fn review_decision_changes_requested_wins_over_approvals() {
+        let list = reviews(&[
+            ("ann", "APPROVED"),
+            ("bob", "CHANGES_REQUESTED"),
+            ("cy", "APPROVED"),
+        ]);
+        assert_eq!(
+            review_decision(&list, false),
+            (Some("CHANGES_REQUESTED".to_string()), 2)
+        );
+    }
+
+    fn run(status: &str, conclusion: Option<&str>) -> CheckRun {
+        CheckRun {
+            status: status.to_string(),
+            conclusion: conclusion.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn combined_checks_reads_check_runs_without_a_status() {
+        let runs = [
+            run("completed", Some("success")),
+            run("completed", Some("skipped")),
+        ];
+        assert_eq!(combined_checks(None, &runs), Some("SUCCESS".to_string()));
+        assert_eq!(combined_checks(None, &[]), None);
+    }
+
+    #[test]
+    fn combined_checks_takes_the_worst_state() {
+        let runs = [run("in_progress", None), run("completed", Some("success"))];
+        assert_eq!(
+            combined_checks(Some("SUCCESS".to_string()), &runs),
+            Some("PENDING".to_string())
+        );
+        let runs = [
+            run("in_progress", None),
+            run("completed", Some("timed_out")),
+        ];
+        assert_eq!(
+            combined_checks(Some("SUCCESS".to_string()), &runs),
+            Some("FAILURE".to_string())
+        );
+        assert_eq!(
+            combined_checks(Some("ERROR".to_string()), &[run("queued", None)]),
+            Some("ERROR".to_string())
+        );
+    }
+
+    #[test]
+    fn review_decision_ignores_comments_and_dismissed_reviews() {
+        let list = reviews(&[
+            ("ann", "APPROVED"),
+            ("ann", "DISMISSED"),
+            ("bob", "COMMENTED"),
+        ]);
+        assert_eq!(review_decision(&list, false), (None, 0));
+        assert_eq!(
+            review_decision(&list, true),
+            (Some("REVIEW_REQUIRED".to_string()), 0)
+        );
+    }
 }
@@ -14,8 +14,15 @@ extern crate serde_derive;
 extern crate structopt;
 #[macro_use]
 extern crate prettytable;
+extern crate chrono;
+extern crate graphql_client;
+extern crate hex;
+extern crate hmac;
+extern crate hyper;
 extern crate notify_rust;
 extern crate regex;
+extern crate sha2;
+extern crate tiny_http;
 use std::collections::HashMap;
 use std::error::Error;
 use std::fs;
@@ -23,7 +30,13 @@ use std::fs::File;
 use std::io::prelude::*;
 use structopt::StructOpt;
 mod aha;
+mod git;
 mod github;
+mod picker;
+mod reference;
+mod retry;
+mod rules;
+mod webhook;
 
 #[derive(StructOpt, Debug)]
 pub struct Opt {
@@ -39,16 +52,47 @@ pub struct Opt {
     config_file: Option<String>,
     #[structopt(short = "g", long = "generate")]
     generate: bool,
+    #[structopt(long = "name")]
+    name: Option<String>,
+    #[structopt(long = "product")]
+    product: Option<String>,
+    #[structopt(long = "release")]
+    release: Option<String>,
+    #[structopt(long = "release-notes")]
+    release_notes: bool,
+    #[structopt(long = "no-release-notes", conflicts_with = "release_notes")]
+    no_release_notes: bool,
+    #[structopt(long = "description-file")]
+    description_file: Option<String>,
+    #[structopt(long = "start")]
+    start: bool,
+    #[structopt(long = "finish")]
+    finish: bool,
+    #[structopt(long = "draft")]
+    draft: bool,
     #[structopt(short = "p", long = "prs")]
     pr_status: bool,
     #[structopt(long = "closed")]
     closed: bool,
+    #[structopt(long = "allow-backwards")]
+    allow_backwards: bool,
+    #[structopt(long = "comment")]
+    comment: bool,
+    #[structopt(long = "label-sync")]
+    label_sync: bool,
+    #[structopt(long = "serve")]
+    serve: bool,
+    #[structopt(long = "listen", default_value = "127.0.0.1:8787")]
+    listen: String,
 }
 #[derive(Debug, Deserialize)]
 struct Config {
     aha: Option<AhaConfig>,
     global_integer: Option<u64>,
     repos: Option<Vec<RepoConfig>>,
+    rules: Option<Vec<rules::Rule>>,
+    branch_template: Option<String>,
+    branch_length: Option<usize>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -56,16 +100,39 @@ struct RepoConfig {
     name: String,
     username: String,
     labels: Option<HashMap<String, String>>,
+    label_priority: Option<Vec<String>>,
+    api_url: Option<String>,
+    references: Option<Vec<reference::ReferenceConfig>>,
+    rules: Option<Vec<rules::Rule>>,
+    allow_backwards: Option<bool>,
+    merged_status: Option<String>,
+    closed_action: Option<aha::ClosedAction>,
+    closed_status: Option<String>,
+    closed_days: Option<i64>,
+    comment: Option<bool>,
+    label_sync: Option<bool>,
+    status_labels: Option<HashMap<String, String>>,
+    pr_template: Option<String>,
+    review_checklist: Option<Vec<String>>,
+    draft: Option<bool>,
+    #[serde(skip)]
+    patterns: reference::ReferencePatterns,
 }
 #[derive(Debug, Deserialize)]
 struct AhaConfig {
     domain: String,
     email: String,
+    workflow_order: Option<Vec<String>>,
+    max_pages: Option<u64>,
+    requests_per_second: Option<f64>,
+    products: Option<Vec<String>>,
 }
 
 #[derive(Deserialize, Debug)]
 struct Env {
     github_api_token: String,
+    github_api_url: Option<String>,
+    github_webhook_secret: Option<String>,
     aha_domain: String,
     aha_token: String,
     workflow_repo: String,
@@ -73,6 +140,117 @@ struct Env {
     workflow_email: String,
 }
 
+fn github_env(
+    client: &reqwest::Client,
+    config: &Env,
+    repo: &RepoConfig,
+    silent: bool,
+    verbose: bool,
+) -> github::GithubEnv {
+    let api_url = repo
+        .api_url
+        .clone()
+        .or_else(|| config.github_api_url.clone())
+        .unwrap_or_else(|| github::DEFAULT_API_URL.to_string());
+    github::GithubEnv {
+        client: client.clone(),
+        github_api_token: config.github_api_token.clone(),
+        api_url,
+        workflow_repo: repo.name.clone(),
+        workflow_login: repo.username.clone(),
+        silent,
+        verbose,
+    }
+}
+
+// the feature --generate or --start recorded for the branch, otherwise the
+// first reference its name holds
+fn branch_reference(
+    branch: &str,
+    recorded: Option<String>,
+    patterns: &reference::ReferencePatterns,
+) -> Option<(String, String)> {
+    match recorded {
+        Some(key) => Some(("feature".to_string(), key)),
+        None => patterns.find(branch).into_iter().next(),
+    }
+}
+
+const DEFAULT_PR_TEMPLATE: &str = "{url}\n\n{description}\n\n{checklist}\n";
+
+// push the current branch, open its pull request and move the feature to
+// in code review
+fn finish(
+    aha: &aha::Aha,
+    repos: &[RepoConfig],
+    github_client: &reqwest::Client,
+    config: &Env,
+    opt: &Opt,
+) -> Result<(), failure::Error> {
+    let branch = git::current_branch(opt)?;
+    let remote = git::remote_repo(opt)?;
+    let repo = repos
+        .iter()
+        .find(|repo| repo.name.eq_ignore_ascii_case(&remote))
+        .ok_or_else(|| format_err!("{} is not in the config", remote))?;
+    let recorded = git::recorded_reference(&branch, opt);
+    let (source, key) = branch_reference(&branch, recorded, &repo.patterns)
+        .ok_or_else(|| format_err!("no aha reference in branch {}", branch))?;
+    let feature = aha.get_record(key.clone(), source.clone())?;
+    let github = github_env(github_client, config, repo, opt.silent, opt.verbose);
+
+    let checklist: Vec<String> = repo
+        .review_checklist
+        .clone()
+        .unwrap_or_else(|| {
+            vec![
+                "[Correctness](https://big.aha.io/products/ENG/pages/ENG-N-53)".to_string(),
+                "[Readability](https://big.aha.io/products/ENG/pages/ENG-N-53)".to_string(),
+                "[Security](https://big.aha.io/products/ENG/pages/ENG-N-103)".to_string(),
+                "[Testing](https://big.aha.io/products/ENG/pages/ENG-N-53)".to_string(),
+            ]
+        })
+        .iter()
+        .map(|item| format!("- [ ] {}", item))
+        .collect();
+    let body = repo
+        .pr_template
+        .as_deref()
+        .unwrap_or(DEFAULT_PR_TEMPLATE)
+        .replace("{reference}", &key)
+        .replace("{name}", feature.name())
+        .replace("{url}", feature.url())
+        .replace("{description}", feature.description())
+        .replace("{checklist}", &checklist.join("\n"));
+    let new_pr = github::NewPullRequest {
+        title: format!("{} {}", key, feature.name()),
+        head: branch.clone(),
+        base: git::default_branch(opt)?,
+        body,
+        draft: opt.draft || repo.draft.unwrap_or(false),
+    };
+
+    git::push_branch(&branch, opt)?;
+    if opt.dry_run {
+        println!("would open {:?}", new_pr);
+        return Ok(());
+    }
+    let number = github::create_pull_request(&github, &new_pr)?;
+    let pr = github::pull_request(&github, number)?;
+    if !opt.silent {
+        println!("opened {}", pr.url);
+    }
+    aha.update_aha(
+        key,
+        &pr,
+        feature,
+        repo,
+        source,
+        Some("In code review".to_string()),
+    )?;
+    Ok(())
+}
+
 fn main() -> Result<(), failure::Error> {
     let opt = Opt::from_args();
     if opt.verbose {
@@ -119,15 +297,36 @@ fn main() -> Result<(), failure::Error> {
     //dotenv::dotenv().ok();
     let my_path = format!("{}/.env", home_dir.display());
     dotenv::from_path(my_path).ok();
-    env_logger::init();
+    if opt.serve {
+        env_logger::Builder::from_env(env_logger::Env::default().filter_or("RUST_LOG", "info"))
+            .init();
+    } else {
+        env_logger::init();
+    }
 
     let mut config: Env = envy::from_env()?;
 
+    let mut workflow_order = None;
+    let mut max_pages = None;
+    let mut requests_per_second = None;
+    let mut products = None;
+    let branch_template = config_info
+        .as_ref()
+        .and_then(|c| c.branch_template.clone())
+        .unwrap_or_else(|| git::DEFAULT_BRANCH_TEMPLATE.to_string());
+    let branch_length = config_info
+        .as_ref()
+        .and_then(|c| c.branch_length)
+        .unwrap_or(git::DEFAULT_BRANCH_LENGTH);
     match config_info.as_ref() {
         Some(c) => match c.aha.as_ref() {
             Some(a) => {
                 config.aha_domain = a.domain.clone();
                 config.workflow_email = a.email.clone();
+                workflow_order = a.workflow_order.clone();
+                max_pages = a.max_pages;
+                requests_per_second = a.requests_per_second;
+                products = a.products.clone();
             }
             _ => (),
         },
@@ -137,17 +336,49 @@ fn main() -> Result<(), failure::Error> {
     if opt.verbose {
         println!("config updated");
     }
-    let repos = match config_info {
-        Some(c) => c.repos.unwrap(),
+    let mut global_rules = None;
+    let mut repos = match config_info {
+        Some(c) => {
+            global_rules = c.rules;
+            c.repos.unwrap()
+        }
         None => vec![RepoConfig {
             name: opt
                 .repo
                 .clone()
                 .expect("Did not pass in required repo param"),
-            username: config.workflow_login,
+            username: config.workflow_login.clone(),
             labels: None,
+            label_priority: None,
+            api_url: None,
+            references: None,
+            rules: None,
+            allow_backwards: None,
+            merged_status: None,
+            closed_action: None,
+            closed_status: None,
+            closed_days: None,
+            comment: None,
+            label_sync: None,
+            status_labels: None,
+            pr_template: None,
+            review_checklist: None,
+            draft: None,
+            patterns: reference::ReferencePatterns::default(),
         }],
     };
+    for repo in repos.iter_mut() {
+        if let Some(references) = &repo.references {
+            repo.patterns = reference::ReferencePatterns::new(references)
+                .map_err(|e| format_err!("{}: {}", repo.name, e))?;
+        } else if let Some(products) = &products {
+            repo.patterns = reference::ReferencePatterns::for_products(products);
+        }
+        // top level rules apply to repos that do not list their own
+        if repo.rules.is_none() {
+            repo.rules = global_rules.clone();
+        }
+    }
 
     if opt.verbose {
         println!("{:?}", repos);
@@ -156,56 +387,145 @@ fn main() -> Result<(), failure::Error> {
     let silent = opt.silent.clone();
     let verbose = opt.verbose.clone();
 
-    let aha = aha::Aha::new(
-        config.aha_domain,
-        config.aha_token,
-        config.workflow_email,
+    let mut aha = aha::Aha::new(
+        config.aha_domain.clone(),
+        config.aha_token.clone(),
+        config.workflow_email.clone(),
         &opt,
-    );
+    )?;
+    aha.workflow_order = workflow_order;
+    aha.max_pages = max_pages;
+    aha.limiter = retry::RateLimiter::new(requests_per_second);
+    aha.products = products;
+    // a server has no desktop to notify
+    aha.notifications = !opt.serve;
+    aha.load_state(format!("{}/.aha_workflow_state", home_dir.display()));
+
+    // syncing, serving and --finish move aha records, catch status typos first
+    if !(opt.pr_status || opt.generate || opt.start) {
+        aha.check_statuses(&repos)?;
+    }
+
+    let github_client = github::client()?;
 
     if opt.pr_status {
         for repo in repos {
-            let github = github::GithubEnv {
-                github_api_token: config.github_api_token.clone(),
-                workflow_repo: repo.name.clone(),
-                workflow_login: repo.username.clone(),
-                silent,
-                verbose: verbose.clone(),
+            let github = github_env(&github_client, &config, &repo, silent, verbose);
+            // the closed history is long and every row costs detail requests
+            let since = if opt.closed {
+                Some(github::closed_since(repo.closed_days.unwrap_or(7)))
+            } else {
+                None
             };
-            let response_body = github::pr_data(&github, "".to_string(), !opt.closed);
+            let response_body = github::pr_data(&github, "".to_string(), !opt.closed, since)?;
             github::pr_table(&response_body);
         }
-    } else if opt.generate {
-        let feature = aha.generate().unwrap()["feature"].take();
-        println!(
-            "{} {}",
-            feature["reference_num"].as_str().unwrap(),
-            feature["url"].as_str().unwrap()
-        );
-        println!(
-            "git stash; git co master; git co -b {}-{}",
-            feature["reference_num"].as_str().unwrap(),
-            feature["name"].as_str().unwrap().to_lowercase().replacen(
-                char::is_whitespace,
-                "-",
-                300
+    } else if opt.generate || opt.start {
+        // nothing is made or moved in aha outside a repository
+        let default = git::check_repository(&opt)?;
+        let branch_for = |feature: &aha::Feature| {
+            git::branch_name(
+                &branch_template,
+                &feature.reference_num,
+                &feature.name,
+                branch_length,
             )
-        );
+        };
+        let (feature, branch) = if opt.start {
+            let feature = match aha.choose_start()? {
+                Some(feature) => feature,
+                None => return Ok(()),
+            };
+            let branch = branch_for(&feature);
+            git::check_branch(&branch, &opt)?;
+            (aha.start(feature)?, branch)
+        } else {
+            // the branch carries a reference aha has not handed out yet, so
+            // it is checked once the feature is made
+            let feature = aha.generate()?;
+            let branch = branch_for(&feature);
+            (feature, branch)
+        };
+        println!("{} {}", feature.reference_num, feature.url);
+        git::start_branch(&branch, &default, &opt)?;
+        git::record_reference(&branch, &feature.reference_num, &opt)?;
+        if !opt.silent {
+            println!("on branch {}", branch);
+        }
+    } else if opt.finish {
+        finish(&aha, &repos, &github_client, &config, &opt)?;
+    } else if opt.serve {
+        let secret = config
+            .github_webhook_secret
+            .clone()
+            .ok_or_else(|| format_err!("GITHUB_WEBHOOK_SECRET is required to serve"))?;
+        let targets: Vec<_> = repos
+            .iter()
+            .map(|repo| {
+                (
+                    repo,
+                    github_env(&github_client, &config, repo, silent, verbose),
+                )
+            })
+            .collect();
+        webhook::serve(&opt.listen, &secret, &aha, &targets)?;
     } else {
         for repo in repos {
-            let labels = repo.labels;
-            let github = github::GithubEnv {
-                github_api_token: config.github_api_token.clone(),
-                workflow_repo: repo.name.clone(),
-                workflow_login: repo.username.clone(),
-                silent,
-                verbose: verbose.clone(),
-            };
-            let list = github::prs(github).unwrap();
-            for pr in list {
-                aha.sync_pr(pr, labels.clone()).unwrap();
+            let github = github_env(&github_client, &config, &repo, silent, verbose);
+            let open = github::prs(github.clone());
+            let closed = github::closed_prs(github.clone(), repo.closed_days.unwrap_or(7));
+            // a closed pull request does not revert what the others carry
+            for list in [&open, &closed].iter().filter_map(|prs| prs.as_ref().ok()) {
+                aha.note_links(list, &repo);
+            }
+            for prs in [open, closed] {
+                match prs {
+                    Ok(list) => {
+                        for pr in list {
+                            if let Err(error) = aha.sync_pr(pr, &repo, &github) {
+                                println!("Error {}: {}", repo.name, error);
+                            }
+                        }
+                    }
+                    Err(error) => println!("Error {}: {}", repo.name, error),
+                }
             }
         }
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn branch_reference_prefers_the_recorded_feature() {
+        let patterns = reference::ReferencePatterns::default();
+        // a branch named before digits were kept apart from the reference
+        let branch = "ENG-12-3-step-checkout";
+        assert_eq!(
+            branch_reference(branch, Some("ENG-12".to_string()), &patterns),
+            Some(("feature".to_string(), "ENG-12".to_string()))
+        );
+        assert_eq!(
+            branch_reference(branch, None, &patterns),
+            Some(("requirement".to_string(), "ENG-12-3".to_string()))
+        );
+    }
+
+    #[test]
+    fn branch_reference_reads_a_generated_branch_as_its_feature() {
+        let patterns = reference::ReferencePatterns::default();
+        let branch = git::branch_name(
+            git::DEFAULT_BRANCH_TEMPLATE,
+            "ENG-12",
+            "3 step checkout",
+            git::DEFAULT_BRANCH_LENGTH,
+        );
+        assert_eq!(
+            branch_reference(&branch, None, &patterns),
+            Some(("feature".to_string(), "ENG-12".to_string()))
+        );
+    }
+}
@@ -0,0 +1,144 @@
+use std::io::{stdin, stdout, Write};
+use termion::event::Key;
+use termion::input::TermRead;
+use termion::raw::IntoRawMode;
+use termion::{clear, cursor, style};
+
+pub struct Choice {
+    pub label: String,
+    pub preview: String,
+}
+
+// type to filter, up and down or ctrl-p and ctrl-n to move, enter to pick,
+// esc or ctrl-c to cancel. None when cancelled.
+pub fn pick(title: &str, choices: &[Choice]) -> Result<Option<usize>, failure::Error> {
+    if !termion::is_tty(&stdin()) {
+        return Err(format_err!("{} needs a terminal", title));
+    }
+    let mut screen = stdout().into_raw_mode()?;
+    let mut query = String::new();
+    let mut selected = 0;
+    let mut keys = stdin().keys();
+    let picked = loop {
+        let matches = filter(&query, choices);
+        selected = selected.min(matches.len().saturating_sub(1));
+        draw(&mut screen, title, &query, choices, &matches, selected)?;
+
+        let key = match keys.next() {
+            Some(key) => key?,
+            None => break None,
+        };
+        match key {
+            Key::Char('\n') => {
+                if let Some(index) = matches.get(selected) {
+                    break Some(*index);
+                }
+            }
+            Key::Esc | Key::Ctrl('c') => break None,
+            Key::Up | Key::Ctrl('p') => selected = selected.saturating_sub(1),
+            Key::Down | Key::Ctrl('n') => selected += 1,
+            Key::Backspace => {
+                query.pop();
+                selected = 0;
+            }
+            Key::Char(c) => {
+                query.push(c);
+                selected = 0;
+            }
+            _ => (),
+        }
+    };
+    write!(
+        screen,
+        "{}{}{}",
+        clear::All,
+        cursor::Goto(1, 1),
+        cursor::Show
+    )?;
+    screen.flush()?;
+    Ok(picked)
+}
+
+// indexes of the choices holding the query's characters in order, tightest
+// matches first
+fn filter(query: &str, choices: &[Choice]) -> Vec<usize> {
+    let mut scored: Vec<(usize, usize)> = choices
+        .iter()
+        .enumerate()
+        .filter_map(|(index, choice)| score(query, &choice.label).map(|score| (score, index)))
+        .collect();
+    scored.sort();
+    scored.into_iter().map(|(_, index)| index).collect()
+}
+
+// characters skipped between the first and last matched one, None when the
+// label does not hold the query
+fn score(query: &str, label: &str) -> Option<usize> {
+    let label: Vec<char> = label.to_lowercase().chars().collect();
+    let mut position = 0;
+    let mut gaps = 0;
+    for (index, c) in query.to_lowercase().chars().enumerate() {
+        let found = label[position..].iter().position(|l| *l == c)?;
+        if index > 0 {
+            gaps += found;
+        }
+        position += found + 1;
+    }
+    Some(gaps)
+}
+
+fn draw<W: Write>(
+    screen: &mut W,
+    title: &str,
+    query: &str,
+    choices: &[Choice],
+    matches: &[usize],
+    selected: usize,
+) -> Result<(), failure::Error> {
+    let (_, height) = termion::terminal_size().unwrap_or((80, 24));
+    // title, query, preview and the help line
+    let rows = (height as usize).saturating_sub(4).max(1);
+    let top = selected.saturating_sub(rows - 1);
+
+    write!(
+        screen,
+        "{}{}{}",
+        cursor::Hide,
+        clear::All,
+        cursor::Goto(1, 1)
+    )?;
+    write!(
+        screen,
+        "{} ({}/{})\r\n",
+        title,
+        matches.len(),
+        choices.len()
+    )?;
+    write!(screen, "> {}\r\n", query)?;
+    for (row, index) in matches.iter().enumerate().skip(top).take(rows) {
+        if row == selected {
+            write!(
+                screen,
+                "{}{}{}\r\n",
+                style::Invert,
+                choices[*index].label,
+                style::Reset
+            )?;
+        } else {
+            write!(screen, "{}\r\n", choices[*index].label)?;
+        }
+    }
+    let preview = matches
+        .get(selected)
+        .map(|index| choices[*index].preview.as_str())
+        .unwrap_or("no matches");
+    write!(
+        screen,
+        "{}{}\r\n",
+        cursor::Goto(1, height.saturating_sub(1).max(1)),
+        preview
+    )?;
+    write!(screen, "enter to pick, esc to cancel")?;
+    screen.flush()?;
+    Ok(())
+}
@@ -0,0 +1,223 @@
+use regex::Regex;
+
+const RECORD_TYPES: [&str; 3] = ["feature", "requirement", "epic"];
+
+// a named capture group per record type, ie (?P<feature>[A-Z]+-\d+)
+#[derive(Debug, Deserialize, Clone)]
+pub struct ReferenceConfig {
+    pub name: String,
+    pub pattern: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct ReferencePatterns {
+    patterns: Vec<Regex>,
+    // "Aha: ENG-1" lines in bodies and commit messages
+    trailer: Regex,
+    link: Regex,
+}
+
+// without product prefixes only a reference leading the title, branch or
+// "Aha:" line counts, so UTF-8 or CVE-2024-1234 later on are not read as one
+impl Default for ReferencePatterns {
+    fn default() -> ReferencePatterns {
+        ReferencePatterns::with_patterns(vec![
+            Regex::new(r"^\s*(?P<requirement>[A-Z]+-\d+-\d+)\b").unwrap(),
+            Regex::new(r"^\s*(?P<feature>[A-Z]+-\d+)\b").unwrap(),
+        ])
+    }
+}
+
+impl ReferencePatterns {
+    pub fn new(configs: &[ReferenceConfig]) -> Result<ReferencePatterns, failure::Error> {
+        let mut patterns = Vec::new();
+        for config in configs {
+            let regex = Regex::new(&config.pattern)
+                .map_err(|e| format_err!("reference pattern {} is invalid: {}", config.name, e))?;
+            let groups: Vec<&str> = regex.capture_names().flatten().collect();
+            if groups.is_empty() {
+                return Err(format_err!(
+                    "reference pattern {} needs a named group, one of {}",
+                    config.name,
+                    RECORD_TYPES.join(", ")
+                ));
+            }
+            if let Some(group) = groups.iter().find(|group| !RECORD_TYPES.contains(group)) {
+                return Err(format_err!(
+                    "reference pattern {} has group {}, expected one of {}",
+                    config.name,
+                    group,
+                    RECORD_TYPES.join(", ")
+                ));
+            }
+            patterns.push(regex);
+        }
+        Ok(ReferencePatterns::with_patterns(patterns))
+    }
+
+    // references anywhere in the text, as long as they carry one of the
+    // product prefixes, ie ENG
+    pub fn for_products(prefixes: &[String]) -> ReferencePatterns {
+        if prefixes.is_empty() {
+            return ReferencePatterns::default();
+        }
+        let prefixes: Vec<String> = prefixes.iter().map(|p| regex::escape(p)).collect();
+        let prefix = prefixes.join("|");
+        ReferencePatterns::with_patterns(vec![
+            Regex::new(&format!(r"\b(?P<requirement>(?:{})-\d+-\d+)\b", prefix)).unwrap(),
+            Regex::new(&format!(r"\b(?P<feature>(?:{})-\d+)\b", prefix)).unwrap(),
+        ])
+    }
+
+    fn with_patterns(patterns: Vec<Regex>) -> ReferencePatterns {
+        ReferencePatterns {
+            patterns,
+            trailer: Regex::new(r"(?mi)^\s*aha:(.*)$").unwrap(),
+            link: Regex::new(r"aha\.io/(features|requirements|epics)/([A-Za-z]+-\d+(?:-\d+)?)")
+                .unwrap(),
+        }
+    }
+
+    // references on "Aha:" lines, and in aha links when links is set
+    pub fn find_in_text(&self, text: &str, links: bool) -> Vec<(String, String)> {
+        let mut found: Vec<(String, String)> = self
+            .trailer
+            .captures_iter(text)
+            .flat_map(|c| self.find(&c[1]))
+            .collect();
+        if links {
+            found.extend(
+                self.link
+                    .captures_iter(text)
+                    .map(|c| (c[1].trim_end_matches('s').to_string(), c[2].to_uppercase())),
+            );
+        }
+        found
+    }
+
+    // (record type, key) in the order they appear. when matches overlap the
+    // earliest and then longest wins, so ENG-1-2 is the requirement and not
+    // also the feature ENG-1
+    pub fn find(&self, text: &str) -> Vec<(String, String)> {
+        let mut matches = Vec::new();
+        for regex in &self.patterns {
+            for captures in regex.captures_iter(text) {
+                for record in RECORD_TYPES.iter() {
+                    if let Some(m) = captures.name(record) {
+                        matches.push((m.start(), m.end(), record.to_string(), m.as_str()));
+                    }
+                }
+            }
+        }
+        matches.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)));
+
+        let mut end = 0;
+        let mut found = Vec::new();
+        for (start, stop, record, key) in matches {
+            if start < end {
+                continue;
+            }
+            end = stop;
+            found.push((record, key.to_uppercase()));
+        }
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair(record: &str, key: &str) -> (String, String) {
+        (record.to_string(), key.to_string())
+    }
+
+    #[test]
+    fn find_prefers_the_requirement_over_its_feature() {
+        let patterns = ReferencePatterns::default();
+        assert_eq!(
+            patterns.find("ENG-1-2 fix the thing"),
+            vec![pair("requirement", "ENG-1-2")]
+        );
+    }
+
+    #[test]
+    fn find_only_reads_a_leading_reference_without_products() {
+        let patterns = ReferencePatterns::default();
+        assert_eq!(
+            patterns.find("Bump UTF-8 parser, fixes CVE-2024-1234"),
+            vec![]
+        );
+        assert_eq!(
+            patterns.find("ENG-3 and ENG-4"),
+            vec![pair("feature", "ENG-3")]
+        );
+    }
+
+    #[test]
+    fn find_only_reads_product_prefixes_anywhere() {
+        let patterns = ReferencePatterns::for_products(&["ENG".to_string()]);
+        assert_eq!(
+            patterns.find("Bump UTF-8 parser, fixes CVE-2024-1234 for ENG-5"),
+            vec![pair("feature", "ENG-5")]
+        );
+    }
+
+    #[test]
+    fn find_keeps_every_reference_in_order() {
+        let patterns = ReferencePatterns::for_products(&["ENG".to_string()]);
+        assert_eq!(
+            patterns.find("ENG-3 and ENG-1-2 then ENG-4"),
+            vec![
+                pair("feature", "ENG-3"),
+                pair("requirement", "ENG-1-2"),
+                pair("feature", "ENG-4"),
+            ]
+        );
+    }
+
+    #[test]
+    fn find_takes_the_longest_of_overlapping_configured_matches() {
+        let patterns = ReferencePatterns::new(&[
+            ReferenceConfig {
+                name: "feature".to_string(),
+                pattern: r"(?i)(?P<feature>eng-\d+)".to_string(),
+            },
+            ReferenceConfig {
+                name: "requirement".to_string(),
+                pattern: r"(?i)(?P<requirement>eng-\d+-\d+)".to_string(),
+            },
+        ])
+        .unwrap();
+        assert_eq!(
+            patterns.find("eng-12-3-fix-login"),
+            vec![pair("requirement", "ENG-12-3")]
+        );
+    }
+
+    #[test]
+    fn find_in_text_reads_trailers_and_links() {
+        let patterns = ReferencePatterns::default();
+        let text =
+            "Fixes the login\n\nAha: ENG-7\nsee https://big.aha.io/requirements/eng-1-2 and ENG-9";
+        assert_eq!(
+            patterns.find_in_text(text, true),
+            vec![pair("feature", "ENG-7"), pair("requirement", "ENG-1-2")]
+        );
+        assert_eq!(
+            patterns.find_in_text(text, false),
+            vec![pair("feature", "ENG-7")]
+        );
+    }
+
+    #[test]
+    fn new_rejects_unknown_or_missing_groups() {
+        let config = |pattern: &str| ReferenceConfig {
+            name: "test".to_string(),
+            pattern: pattern.to_string(),
+        };
+        assert!(ReferencePatterns::new(&[config(r"ENG-\d+")]).is_err());
+        assert!(ReferencePatterns::new(&[config(r"(?P<story>ENG-\d+)")]).is_err());
+        assert!(ReferencePatterns::new(&[config(r"(?P<feature>ENG-\d+")]).is_err());
+    }
+}
@@ -0,0 +1,261 @@
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::StatusCode;
+use std::cell::Cell;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const MAX_RETRIES: u32 = 5;
+const MAX_BACKOFF_SECS: u64 = 60;
+
+// how the aha and github clients send a request: 5xx and rate limited
+// responses are retried, anything else non 2xx is handed back
+pub struct Retry<'a> {
+    // names the api in retry messages
+    pub service: &'a str,
+    pub silent: bool,
+    pub limiter: Option<&'a RateLimiter>,
+}
+
+#[derive(Debug)]
+pub enum Failure {
+    // no response came back
+    Transport(reqwest::Error),
+    // the last response was not a 2xx, with its body
+    Status {
+        status: StatusCode,
+        url: String,
+        text: String,
+        attempts: u32,
+    },
+}
+
+impl<'a> Retry<'a> {
+    // repeatable is false for posts that create something. a lost response
+    // may still have created it, so only a failed connect or a rate limit is
+    // retried
+    pub fn send<F>(&self, build: F, repeatable: bool) -> Result<reqwest::Response, Failure>
+    where
+        F: Fn() -> reqwest::RequestBuilder,
+    {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            if let Some(limiter) = self.limiter {
+                limiter.wait();
+            }
+            let mut response = match build().send() {
+                Ok(response) => response,
+                Err(error) if attempt <= MAX_RETRIES && (repeatable || not_sent(&error)) => {
+                    self.retry_in(backoff(attempt), &format!("request failed: {}", error));
+                    continue;
+                }
+                Err(error) => return Err(Failure::Transport(error)),
+            };
+            let status = response.status();
+            let headers = response.headers().clone();
+            if status.is_success() {
+                // out of requests for this window, wait it out before the next
+                if header_u64(&headers, "x-ratelimit-remaining") == Some(0) {
+                    let wait = rate_limit_reset(&headers).unwrap_or_else(|| backoff(1));
+                    match self.limiter {
+                        Some(limiter) => limiter.pause(wait),
+                        None => self.retry_in(wait, "rate limit exhausted"),
+                    }
+                }
+                return Ok(response);
+            }
+
+            let text = response.text().unwrap_or_default();
+            let limited = rate_limited(status, &headers, &text);
+            if attempt > MAX_RETRIES || !(limited || (repeatable && status.is_server_error())) {
+                return Err(Failure::Status {
+                    status,
+                    url: response.url().to_string(),
+                    text,
+                    attempts: attempt,
+                });
+            }
+            let wait = retry_after(status, &headers).unwrap_or_else(|| backoff(attempt));
+            self.retry_in(wait, &format!("{} from {}", status, response.url()));
+        }
+    }
+
+    fn retry_in(&self, wait: Duration, reason: &str) {
+        if !self.silent {
+            println!(
+                "{} {}, retrying in {:.1}s",
+                self.service,
+                reason,
+                wait.as_secs_f64()
+            );
+        }
+        thread::sleep(wait);
+    }
+}
+
+// spaces requests out to a requests_per_second budget and holds them back
+// while the api's rate limit window is used up
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    pub requests_per_second: Option<f64>,
+    next: Cell<Option<Instant>>,
+}
+
+impl RateLimiter {
+    pub fn new(requests_per_second: Option<f64>) -> RateLimiter {
+        RateLimiter {
+            requests_per_second: requests_per_second.filter(|rps| *rps > 0.0),
+            next: Cell::new(None),
+        }
+    }
+
+    fn wait(&self) {
+        let now = Instant::now();
+        if let Some(next) = self.next.get() {
+            if next > now {
+                thread::sleep(next - now);
+            }
+        }
+        if let Some(rps) = self.requests_per_second {
+            let start = self.next.get().map_or(now, |next| next.max(now));
+            self.next
+                .set(Some(start + Duration::from_secs_f64(1.0 / rps)));
+        }
+    }
+
+    fn pause(&self, wait: Duration) {
+        let until = Instant::now() + wait;
+        if self.next.get().is_none_or(|next| next < until) {
+            self.next.set(Some(until));
+        }
+    }
+}
+
+// the connection failed before any of the request went out, so sending it
+// again can not repeat a write
+fn not_sent(error: &reqwest::Error) -> bool {
+    error
+        .get_ref()
+        .and_then(|error| error.downcast_ref::<hyper::Error>())
+        .is_some_and(|error| error.is_connect())
+}
+
+// aha answers 429, github's primary and secondary limits come back as 403
+fn rate_limited(status: StatusCode, headers: &HeaderMap, text: &str) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS
+        || (status == StatusCode::FORBIDDEN
+            && (headers.contains_key(RETRY_AFTER)
+                || header_u64(headers, "x-ratelimit-remaining") == Some(0)
+                || text.contains("rate limit")
+                || text.contains("abuse")))
+}
+
+// what the response asks for, the reset header also comes with successes and
+// 5xx where it is the end of an unused window
+fn retry_after(status: StatusCode, headers: &HeaderMap) -> Option<Duration> {
+    if let Some(secs) = header_u64(headers, RETRY_AFTER.as_str()) {
+        return Some(Duration::from_secs(secs));
+    }
+    if status == StatusCode::TOO_MANY_REQUESTS
+        || header_u64(headers, "x-ratelimit-remaining") == Some(0)
+    {
+        return rate_limit_reset(headers);
+    }
+    None
+}
+
+fn header_u64(headers: &HeaderMap, name: &str) -> Option<u64> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok())
+}
+
+// x-ratelimit-reset is the epoch second the window opens again
+fn rate_limit_reset(headers: &HeaderMap) -> Option<Duration> {
+    let reset = header_u64(headers, "x-ratelimit-reset")?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+    Some(Duration::from_secs(reset.saturating_sub(now) + 1))
+}
+
+// doubling from 1s with up to half of it again as jitter, so parallel
+// syncs do not retry in step
+fn backoff(attempt: u32) -> Duration {
+    let base = Duration::from_secs((1u64 << attempt.min(6)).min(MAX_BACKOFF_SECS));
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.subsec_nanos())
+        .unwrap_or(0);
+    base + base / 2 * (nanos % 1000) / 1000
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn rate_limited_knows_both_apis() {
+        let none = headers(&[]);
+        assert!(rate_limited(StatusCode::TOO_MANY_REQUESTS, &none, ""));
+        assert!(rate_limited(
+            StatusCode::FORBIDDEN,
+            &headers(&[("retry-after", "30")]),
+            ""
+        ));
+        assert!(rate_limited(
+            StatusCode::FORBIDDEN,
+            &headers(&[("x-ratelimit-remaining", "0")]),
+            ""
+        ));
+        assert!(rate_limited(
+            StatusCode::FORBIDDEN,
+            &none,
+            "You have exceeded a secondary rate limit"
+        ));
+        assert!(!rate_limited(StatusCode::FORBIDDEN, &none, "Forbidden"));
+        assert!(!rate_limited(
+            StatusCode::BAD_GATEWAY,
+            &headers(&[("x-ratelimit-remaining", "0")]),
+            ""
+        ));
+    }
+
+    #[test]
+    fn retry_after_ignores_the_reset_of_an_unused_window() {
+        assert_eq!(
+            retry_after(
+                StatusCode::FORBIDDEN,
+                &headers(&[("retry-after", "30"), ("x-ratelimit-reset", "1")])
+            ),
+            Some(Duration::from_secs(30))
+        );
+        let unused = headers(&[
+            ("x-ratelimit-remaining", "4999"),
+            ("x-ratelimit-reset", "1"),
+        ]);
+        assert_eq!(retry_after(StatusCode::BAD_GATEWAY, &unused), None);
+        let spent = headers(&[("x-ratelimit-remaining", "0"), ("x-ratelimit-reset", "1")]);
+        assert_eq!(
+            retry_after(StatusCode::FORBIDDEN, &spent),
+            Some(Duration::from_secs(1))
+        );
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        for (attempt, secs) in &[(1, 2), (2, 4), (5, 32), (6, 60), (9, 60)] {
+            let wait = backoff(*attempt);
+            assert!(wait >= Duration::from_secs(*secs));
+            assert!(wait <= Duration::from_secs(*secs) * 3 / 2);
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use super::github::PullRequest;
+
+// every condition that is set has to hold for the rule to match, ie
+//
+// [[rules]]
+// name="approved and green"
+// status="Ready to ship"
+// draft=false
+// min_approvals=1
+// changes_requested=false
+// checks="success"
+#[derive(Debug, Deserialize, Clone)]
+pub struct Rule {
+    pub name: String,
+    pub status: String,
+    pub draft: Option<bool>,
+    pub min_approvals: Option<usize>,
+    pub changes_requested: Option<bool>,
+    // the head commit's statuses and check runs together: success, pending,
+    // failure, error, expected or none
+    pub checks: Option<String>,
+    // open or merged, closed pull requests go through closed_status instead
+    pub state: Option<String>,
+    pub labels: Option<Vec<String>>,
+    pub without_labels: Option<Vec<String>>,
+    pub current_status: Option<Vec<String>>,
+}
+
+impl Rule {
+    // the first condition that does not hold, None when the rule matches
+    pub fn mismatch(&self, pr: &PullRequest, current_status: &str) -> Option<String> {
+        if let Some(draft) = self.draft {
+            if pr.draft != draft {
+                return Some(format!("draft is {}", pr.draft));
+            }
+        }
+        if let Some(min) = self.min_approvals {
+            if pr.approvals < min {
+                return Some(format!("{} of {} approvals", pr.approvals, min));
+            }
+        }
+        if let Some(wanted) = self.changes_requested {
+            let requested = pr.review_decision.as_deref() == Some("CHANGES_REQUESTED");
+            if requested != wanted {
+                return Some(format!("changes requested is {}", requested));
+            }
+        }
+        if let Some(checks) = &self.checks {
+            let actual = pr.checks.clone().unwrap_or_else(|| "none".to_string());
+            if !actual.eq_ignore_ascii_case(checks) {
+                return Some(format!("checks are {}", actual.to_lowercase()));
+            }
+        }
+        if let Some(state) = &self.state {
+            if !pr.state.eq_ignore_ascii_case(state) {
+                return Some(format!("state is {}", pr.state.to_lowercase()));
+            }
+        }
+        if let Some(labels) = &self.labels {
+            if let Some(missing) = labels.iter().find(|label| !pr.labels.contains(label)) {
+                return Some(format!("missing label {}", missing));
+            }
+        }
+        if let Some(labels) = &self.without_labels {
+            if let Some(found) = labels.iter().find(|label| pr.labels.contains(label)) {
+                return Some(format!("has label {}", found));
+            }
+        }
+        if let Some(statuses) = &self.current_status {
+            if !statuses.iter().any(|status| status == current_status) {
+                return Some(format!("aha status is {}", current_status));
+            }
+        }
+        None
+    }
+}
+
+// rules are tried in order and the first match wins
+pub fn evaluate<'r>(
+    rules: &'r [Rule],
+    pr: &PullRequest,
+    current_status: &str,
+    explain: bool,
+) -> Option<&'r Rule> {
+    for rule in rules {
+        match rule.mismatch(pr, current_status) {
+            None => {
+                if explain {
+                    println!("rule {} matched #{}: {}", rule.name, pr.number, rule.status);
+                }
+                return Some(rule);
+            }
+            Some(reason) => {
+                if explain {
+                    println!("rule {} skipped #{}: {}", rule.name, pr.number, reason);
+                }
+            }
+        }
+    }
+    if explain && !rules.is_empty() {
+        println!("no rule matched #{}", pr.number);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pr() -> PullRequest {
+        PullRequest {
+            number: 1,
+            name: "ENG-1 login".to_string(),
+            url: "https://github.com/big/test/pull/1".to_string(),
+            labels: vec!["Ready".to_string()],
+            head_ref: "ENG-1-login".to_string(),
+            body: String::new(),
+            commits: vec![],
+            state: "OPEN".to_string(),
+            draft: false,
+            approvals: 1,
+            review_decision: Some("APPROVED".to_string()),
+            checks: Some("SUCCESS".to_string()),
+        }
+    }
+
+    fn rule(name: &str, status: &str) -> Rule {
+        Rule {
+            name: name.to_string(),
+            status: status.to_string(),
+            draft: None,
+            min_approvals: None,
+            changes_requested: None,
+            checks: None,
+            state: None,
+            labels: None,
+            without_labels: None,
+            current_status: None,
+        }
+    }
+
+    #[test]
+    fn evaluate_takes_the_first_matching_rule() {
+        let mut draft = rule("draft", "In development");
+        draft.draft = Some(true);
+        let mut approved = rule("approved and green", "Ready to ship");
+        approved.min_approvals = Some(1);
+        approved.checks = Some("success".to_string());
+        let fallback = rule("anything", "In code review");
+        let rules = vec![draft, approved, fallback];
+        let matched = evaluate(&rules, &pr(), "In code review", false).unwrap();
+        assert_eq!(matched.name, "approved and green");
+    }
+
+    #[test]
+    fn evaluate_needs_every_condition() {
+        let mut approved = rule("approved and green", "Ready to ship");
+        approved.min_approvals = Some(2);
+        approved.checks = Some("success".to_string());
+        assert!(evaluate(&[approved.clone()], &pr(), "In code review", false).is_none());
+
+        approved.min_approvals = Some(1);
+        let mut failing = pr();
+        failing.checks = Some("FAILURE".to_string());
+        assert!(evaluate(&[approved.clone()], &failing, "In code review", false).is_none());
+        failing.checks = None;
+        assert!(evaluate(&[approved], &failing, "In code review", false).is_none());
+    }
+
+    #[test]
+    fn mismatch_explains_the_first_failed_condition() {
+        let mut labelled = rule("labelled", "In PM review");
+        labelled.labels = Some(vec!["Ready".to_string(), "Needs PM review".to_string()]);
+        assert_eq!(
+            labelled.mismatch(&pr(), "In code review"),
+            Some("missing label Needs PM review".to_string())
+        );
+
+        let mut current = rule("current", "Ready to ship");
+        current.state = Some("open".to_string());
+        current.without_labels = Some(vec!["Blocked".to_string()]);
+        current.current_status = Some(vec!["In PM review".to_string()]);
+        assert_eq!(
+            current.mismatch(&pr(), "In code review"),
+            Some("aha status is In code review".to_string())
+        );
+        assert_eq!(current.mismatch(&pr(), "In PM review"), None);
+    }
+}
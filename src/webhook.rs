@@ -0,0 +1,298 @@
+use super::aha::Aha;
+use super::github;
+use super::RepoConfig;
+use hmac::{Hmac, Mac};
+use log::{info, warn};
+use serde_json::Value;
+use sha2::Sha256;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use tiny_http::{Method, Request, Response, Server};
+
+// an event the server accepted, synced after github has its answer
+type Event = (String, Value);
+
+// GET /health for the proxy, github posts events to /webhook. github gives up
+// on a delivery after ten seconds and a sync can take longer, so the server
+// thread only checks and queues events and this one syncs them
+pub fn serve(
+    listen: &str,
+    secret: &str,
+    aha: &Aha,
+    repos: &[(&RepoConfig, github::GithubEnv)],
+) -> Result<(), failure::Error> {
+    let server =
+        Server::http(listen).map_err(|e| format_err!("could not listen on {}: {}", listen, e))?;
+    info!("event=listening addr={}", listen);
+    let (sender, receiver) = mpsc::channel();
+    let secret = secret.to_string();
+    thread::spawn(move || {
+        for mut request in server.incoming_requests() {
+            let (status, message) = handle(&mut request, &secret, &sender);
+            info!(
+                "event=request method={} path={} status={} result={:?}",
+                request.method(),
+                request.url(),
+                status,
+                message
+            );
+            let response = Response::from_string(message).with_status_code(status);
+            if let Err(e) = request.respond(response) {
+                warn!("event=respond_failed error={:?}", e.to_string());
+            }
+        }
+    });
+    for (event, payload) in receiver {
+        let (status, message) = sync_event(&event, &payload, aha, repos);
+        info!(
+            "event=synced github_event={} status={} result={:?}",
+            event, status, message
+        );
+    }
+    Ok(())
+}
+
+fn handle(request: &mut Request, secret: &str, sender: &Sender<Event>) -> (u16, String) {
+    match (request.method(), request.url()) {
+        (Method::Get, "/health") => (200, "ok".to_string()),
+        (Method::Post, "/webhook") => {
+            let mut body = Vec::new();
+            if let Err(e) = request.as_reader().read_to_end(&mut body) {
+                return (400, format!("could not read body: {}", e));
+            }
+            let event = header(request, "X-GitHub-Event").unwrap_or("");
+            let delivery = header(request, "X-GitHub-Delivery").unwrap_or("");
+            info!(
+                "event=delivery github_event={} delivery={}",
+                event, delivery
+            );
+            let signature = header(request, "X-Hub-Signature-256");
+            queue(secret, event, &body, signature, sender)
+        }
+        _ => (404, "not found".to_string()),
+    }
+}
+
+fn queue(
+    secret: &str,
+    event: &str,
+    body: &[u8],
+    signature: Option<&str>,
+    sender: &Sender<Event>,
+) -> (u16, String) {
+    if !verify(secret, body, signature) {
+        return (401, "bad signature".to_string());
+    }
+    let payload = match serde_json::from_slice(body) {
+        Ok(payload) => payload,
+        Err(e) => return (400, format!("could not parse payload: {}", e)),
+    };
+    if event == "ping" {
+        return (200, "pong".to_string());
+    }
+    match sender.send((event.to_string(), payload)) {
+        Ok(()) => (202, format!("queued {}", event)),
+        Err(_) => (503, "not syncing".to_string()),
+    }
+}
+
+fn header<'r>(request: &'r Request, name: &str) -> Option<&'r str> {
+    request
+        .headers()
+        .iter()
+        .find(|h| h.field.as_str().as_str().eq_ignore_ascii_case(name))
+        .map(|h| h.value.as_str())
+}
+
+// X-Hub-Signature-256 is sha256=<hex hmac of the body keyed by the secret>
+fn verify(secret: &str, body: &[u8], signature: Option<&str>) -> bool {
+    let signature = match signature
+        .and_then(|s| s.strip_prefix("sha256="))
+        .and_then(|s| hex::decode(s).ok())
+    {
+        Some(signature) => signature,
+        None => return false,
+    };
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac takes keys of any size");
+    mac.update(body);
+    mac.verify_slice(&signature).is_ok()
+}
+
+fn sync_event(
+    event: &str,
+    payload: &Value,
+    aha: &Aha,
+    repos: &[(&RepoConfig, github::GithubEnv)],
+) -> (u16, String) {
+    let full_name = payload["repository"]["full_name"].as_str().unwrap_or("");
+    let target = repos
+        .iter()
+        .find(|(repo, _)| repo.name.eq_ignore_ascii_case(full_name));
+    let (repo, github) = match target {
+        Some(target) => target,
+        None => return (202, format!("{} is not configured", full_name)),
+    };
+
+    match event {
+        "pull_request" | "pull_request_review" => {
+            let number = match payload["pull_request"]["number"].as_i64() {
+                Some(number) => number,
+                None => return (400, "missing pull_request.number".to_string()),
+            };
+            let author = payload["pull_request"]["user"]["login"]
+                .as_str()
+                .unwrap_or("");
+            if author != repo.username {
+                return (202, format!("#{} is by {}", number, author));
+            }
+            info!(
+                "event=sync repo={} pr={} action={}",
+                repo.name,
+                number,
+                payload["action"].as_str().unwrap_or("")
+            );
+            match github::pull_request(github, number) {
+                Ok(pr) => {
+                    // a closed pull request does not revert what the open or
+                    // recently merged ones carry, the server outlives any one
+                    // list of them
+                    if pr.state == "CLOSED" {
+                        aha.live_links.borrow_mut().clear();
+                        let days = repo.closed_days.unwrap_or(7);
+                        for prs in [
+                            github::prs(github.clone()),
+                            github::closed_prs(github.clone(), days),
+                        ] {
+                            match prs {
+                                Ok(list) => aha.note_links(&list, repo),
+                                Err(e) => {
+                                    return (502, format!("could not load pull requests: {}", e))
+                                }
+                            }
+                        }
+                    }
+                    match aha.sync_pr(pr, repo, github) {
+                        Ok(()) => (200, format!("synced #{}", number)),
+                        Err(e) => (500, format!("sync #{} failed: {}", number, e)),
+                    }
+                }
+                Err(e) => (502, format!("could not load #{}: {}", number, e)),
+            }
+        }
+        // a label was renamed or removed, every open pull request may change
+        "label" => {
+            info!("event=sync repo={} pr=all action=label", repo.name);
+            match github::prs(github.clone()) {
+                Ok(list) => {
+                    let count = list.len();
+                    for pr in list {
+                        if let Err(e) = aha.sync_pr(pr, repo, github) {
+                            warn!(
+                                "event=sync_failed repo={} error={:?}",
+                                repo.name,
+                                e.to_string()
+                            );
+                        }
+                    }
+                    (200, format!("synced {} pull requests", count))
+                }
+                Err(e) => (502, format!("could not load pull requests: {}", e)),
+            }
+        }
+        _ => (202, format!("ignored {}", event)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sign(secret: &str, body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(body);
+        format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+    }
+
+    #[test]
+    fn verify_accepts_a_valid_signature() {
+        let body = br#"{"action":"opened"}"#;
+        let signature = sign("secret", body);
+        assert!(verify("secret", body, Some(&signature)));
+    }
+
+    #[test]
+    fn verify_rejects_another_secret_or_body() {
+        let body = br#"{"action":"opened"}"#;
+        let signature = sign("other", body);
+        assert!(!verify("secret", body, Some(&signature)));
+        let signature = sign("secret", body);
+        assert!(!verify(
+            "secret",
+            br#"{"action":"closed"}"#,
+            Some(&signature)
+        ));
+    }
+
+    #[test]
+    fn verify_rejects_a_missing_prefix() {
+        let body = b"body";
+        let signature = sign("secret", body);
+        let bare = signature.trim_start_matches("sha256=");
+        assert!(!verify("secret", body, Some(bare)));
+        assert!(!verify(
+            "secret",
+            body,
+            Some(&signature.replace("sha256=", "sha1="))
+        ));
+        assert!(!verify("secret", body, None));
+    }
+
+    #[test]
+    fn queue_hands_signed_events_on() {
+        let (sender, receiver) = mpsc::channel();
+        let body = br#"{"action":"opened"}"#;
+        let signature = sign("secret", body);
+        let (status, _) = queue("secret", "pull_request", body, Some(&signature), &sender);
+        assert_eq!(status, 202);
+        let (event, payload) = receiver.try_recv().unwrap();
+        assert_eq!(event, "pull_request");
+        assert_eq!(payload["action"], "opened");
+    }
+
+    #[test]
+    fn queue_answers_the_rest_itself() {
+        let (sender, receiver) = mpsc::channel();
+        let body = br#"{"zen":"hi"}"#;
+        let signature = sign("secret", body);
+        assert_eq!(
+            queue("secret", "ping", body, Some(&signature), &sender).0,
+            200
+        );
+        assert_eq!(
+            queue("other", "push", body, Some(&signature), &sender).0,
+            401
+        );
+        let body = b"not json";
+        let signature = sign("secret", body);
+        assert_eq!(
+            queue("secret", "push", body, Some(&signature), &sender).0,
+            400
+        );
+        assert!(receiver.try_recv().is_err());
+        drop(receiver);
+        let body = br#"{}"#;
+        let signature = sign("secret", body);
+        assert_eq!(
+            queue("secret", "push", body, Some(&signature), &sender).0,
+            503
+        );
+    }
+
+    #[test]
+    fn verify_rejects_bad_hex() {
+        assert!(!verify("secret", b"body", Some("sha256=not hex")));
+        assert!(!verify("secret", b"body", Some("sha256=abc")));
+        assert!(!verify("secret", b"body", Some("sha256=")));
+    }
+}